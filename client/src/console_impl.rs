@@ -2,13 +2,6 @@ use esprit2::prelude::*;
 use std::collections::VecDeque;
 use std::sync::mpsc;
 
-#[derive(Debug, Clone)]
-pub(crate) struct Dummy;
-
-impl console::Handle for Dummy {
-	fn send_message(&self, _message: console::Message) {}
-}
-
 #[derive(Debug, Clone)]
 pub(crate) struct Handle {
 	sender: mpsc::Sender<console::Message>,
@@ -224,19 +224,51 @@ impl<'canvas> Context<'canvas> {
 	}
 
 	pub(crate) fn console(&mut self, console: &Console, colors: &crate::options::ConsoleColors) {
-		let canvas = &mut self.canvas;
+		/// Matches the metrics used by `label_color`.
+		const LINE_HEIGHT: i32 = 20;
+
 		let rect = Rect::new(
 			self.x,
 			self.y,
 			(self.rect.right() - self.x) as u32,
 			(self.rect.bottom() - self.y) as u32,
 		);
-		let font_texture_creator = canvas.texture_creator();
-		canvas.set_clip_rect(rect);
+		self.canvas.set_clip_rect(rect);
 
+		// Newest messages are at the bottom, so draw upwards until the console is full.
 		let mut cursor = rect.y + (rect.height() as i32);
+		for message in console.history.iter().rev() {
+			if cursor <= rect.y {
+				break;
+			}
+			cursor -= LINE_HEIGHT;
+			let (text, color) = match &message.printer {
+				console::MessagePrinter::Console(color) => {
+					(message.text.to_string(), colors.get(color))
+				}
+				console::MessagePrinter::Dialogue { speaker, progress } => {
+					let shown = message
+						.text
+						.char_indices()
+						.nth(*progress as usize)
+						.map_or(&*message.text, |(i, _)| &message.text[..i]);
+					(format!("{speaker}: {shown}"), colors.normal)
+				}
+				console::MessagePrinter::Combat(log) => {
+					(format!("{} ({log})", message.text), colors.combat)
+				}
+				console::MessagePrinter::Chat { username } => {
+					(format!("<{username}> {}", message.text), colors.normal)
+				}
+			};
+			Context::new(
+				self.canvas,
+				Rect::new(rect.x, cursor, rect.width(), LINE_HEIGHT as u32),
+			)
+			.label_color(&text, color);
+		}
 
-		canvas.set_clip_rect(None);
+		self.canvas.set_clip_rect(None);
 	}
 }
 
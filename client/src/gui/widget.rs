@@ -95,7 +95,7 @@ pub(crate) fn menu(
 					character_info(menu, &selected_character.borrow(), lua);
 				};
 				let mut buff_fn = |menu: &mut gui::Context| {
-					character_buffs(menu, &selected_character.borrow(), resources, textures);
+					character_buffs(menu, &selected_character.borrow(), resources, lua, textures);
 				};
 				menu.hsplit(&mut [
 					Some((&mut character_fn) as &mut dyn FnMut(&mut gui::Context)),
@@ -249,7 +249,7 @@ impl Pamphlet {
 						layout.flipped,
 						|player_window| {
 							character_info(player_window, &piece, lua);
-							character_buffs(player_window, &piece, resources, textures);
+							character_buffs(player_window, &piece, resources, lua, textures);
 						},
 					);
 				});
@@ -404,6 +404,8 @@ fn character_info(player_window: &mut gui::Context<'_>, piece: &character::Piece
 		sheet: character::Sheet { nouns, .. },
 		hp,
 		sp,
+		level,
+		experience,
 		..
 	} = piece;
 	let name = &nouns.name;
@@ -417,23 +419,24 @@ fn character_info(player_window: &mut gui::Context<'_>, piece: &character::Piece
 				magic,
 				resistance,
 			},
-		buffs,
-		debuffs,
+		..
 	}) = piece.stat_outcomes(lua)
 	else {
 		return;
 	};
+	let base = piece.sheet.stats;
 
-	let get_color = |buff, debuff| {
-		if buff > debuff {
+	let get_color = |stat: u16, base: u16| {
+		if stat > base {
 			(0, 0, 255, 255)
-		} else if debuff > 0 {
+		} else if stat < base {
 			(255, 0, 0, 255)
 		} else {
 			(255, 255, 255, 255)
 		}
 	};
 
+	player_window.label(&format!("Level {level} ({experience} exp)"));
 	player_window.label(&format!("HP: {hp}/{heart}"));
 	player_window.progress_bar(
 		(*hp as f32) / (heart as f32),
@@ -450,17 +453,14 @@ fn character_info(player_window: &mut gui::Context<'_>, piece: &character::Piece
 		10,
 		5,
 	);
-	let physical_stat_info = [
-		("Pwr", power, buffs.power, debuffs.power),
-		("Def", defense, buffs.defense, debuffs.defense),
-	];
+	let physical_stat_info = [("Pwr", power, base.power), ("Def", defense, base.defense)];
 	let mut physical_stats = [None, None];
-	for ((stat_name, stat, buff, debuff), stat_half) in physical_stat_info
+	for ((stat_name, stat, base), stat_half) in physical_stat_info
 		.into_iter()
 		.zip(physical_stats.iter_mut())
 	{
 		*stat_half = Some(move |stat_half: &mut gui::Context| {
-			let color = get_color(buff, debuff);
+			let color = get_color(stat, base);
 			stat_half.horizontal();
 			stat_half.label_color(&stat.to_string(), color);
 			stat_half.advance(4, 0);
@@ -469,15 +469,15 @@ fn character_info(player_window: &mut gui::Context<'_>, piece: &character::Piece
 	}
 	player_window.hsplit(&mut physical_stats);
 	let magical_stat_info = [
-		("Mag", magic, buffs.magic, debuffs.magic),
-		("Res", resistance, buffs.resistance, debuffs.resistance),
+		("Mag", magic, base.magic),
+		("Res", resistance, base.resistance),
 	];
 	let mut magical_stats = [None, None];
-	for ((stat_name, stat, buff, debuff), stat_half) in
+	for ((stat_name, stat, base), stat_half) in
 		magical_stat_info.into_iter().zip(magical_stats.iter_mut())
 	{
 		*stat_half = Some(move |stat_half: &mut gui::Context| {
-			let color = get_color(buff, debuff);
+			let color = get_color(stat, base);
 			stat_half.horizontal();
 			stat_half.label_color(&stat.to_string(), color);
 			stat_half.advance(4, 0);
@@ -491,13 +491,14 @@ fn character_buffs(
 	gui: &mut gui::Context,
 	piece: &character::Piece,
 	resources: &resource::Manager,
+	lua: &mlua::Lua,
 	textures: &texture::Manager,
 ) {
 	let components = piece
 		.components
 		.keys()
-		.filter_map(|x| resources.component.get(x).ok())
-		.filter(|x| x.visible)
+		.filter_map(|id| resources.component.get(id).ok().map(|x| (id, x)))
+		.filter(|(_, x)| x.visible)
 		.peekable();
 	{
 		let mut components = components.clone();
@@ -505,7 +506,7 @@ fn character_buffs(
 			let textures_per_row = gui.rect.width() / (32 + 8);
 			gui.horizontal();
 			for _ in 0..textures_per_row {
-				if let Some(component) = components.next()
+				if let Some((_, component)) = components.next()
 					&& let Some(icon) = &component.icon
 				{
 					gui.htexture(textures.get(icon), 32);
@@ -516,7 +517,55 @@ fn character_buffs(
 			gui.advance(8, 8);
 		}
 	}
-	for component in components {
-		gui.label(&component.name);
+	let sources = piece
+		.stat_outcomes(lua)
+		.map(|x| x.sources)
+		.unwrap_or_default();
+	for (id, component) in components {
+		let modifiers = sources
+			.iter()
+			.find(|x| x.component == *id)
+			.map(describe_modifier)
+			.unwrap_or_default();
+		if modifiers.is_empty() {
+			gui.label(&component.name);
+		} else {
+			gui.label(&format!("{} ({modifiers})", component.name));
+		}
+	}
+}
+
+/// Summarizes a component's stat modifiers, such as "+2 Pwr, -25% Def".
+fn describe_modifier(modifier: &character::Modifier) -> String {
+	let character::Modifier {
+		buff,
+		buff_percent,
+		debuff,
+		debuff_percent,
+		..
+	} = modifier;
+	let values = |x: &character::Stats| {
+		[
+			("HP", x.heart),
+			("SP", x.soul),
+			("Pwr", x.power),
+			("Def", x.defense),
+			("Mag", x.magic),
+			("Res", x.resistance),
+		]
+	};
+	let mut parts = Vec::new();
+	for (sign, unit, stats) in [
+		('+', "", buff),
+		('+', "%", buff_percent),
+		('-', "", debuff),
+		('-', "%", debuff_percent),
+	] {
+		for (name, amount) in values(stats) {
+			if amount > 0 {
+				parts.push(format!("{sign}{amount}{unit} {name}"));
+			}
+		}
 	}
+	parts.join(", ")
 }
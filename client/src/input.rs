@@ -301,11 +301,6 @@ pub(crate) fn controllable_character(
 				}
 			}
 
-			if options.controls.talk.contains(keycode) {
-				console.say("Luvui", "Meow!");
-				console.say("Aris", "I am a kitty :3");
-			}
-
 			if options.controls.autocombat.contains(keycode) {
 				if let Some(action) = world.consider_action(lua, world.next_character().clone())? {
 					Ok((Mode::Normal, Some(Response::Action(action))))
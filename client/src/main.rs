@@ -22,7 +22,8 @@ use std::net::{Ipv4Addr, SocketAddr};
 use std::process::exit;
 use std::{fs, io, thread};
 use tokio::net::{TcpListener, TcpStream};
-use tokio::sync::mpsc;
+use tokio::select;
+use tokio::sync::{mpsc, watch};
 use tokio::task;
 use tokio_stream::wrappers::ReceiverStream;
 use tracing::Instrument;
@@ -169,8 +170,11 @@ pub(crate) async fn main() {
 									input::Mode::Normal,
 									ServerHandle::new(
 										stream,
-										ClientAuthentication { username },
-										None,
+										ClientAuthentication {
+											username,
+											password: None,
+										},
+										server_handle::Destination::New,
 										&lua,
 										texture::Manager::new(&texture_creator),
 									)
@@ -184,14 +188,18 @@ pub(crate) async fn main() {
 								url,
 							}) => {
 								let (client_routing, address) = ClientRouting::new(&url).unwrap();
+								let password = ClientRouting::account_password(&url).unwrap();
 								let stream = TcpStream::connect(address).await.unwrap();
 								server = Some((
 									input::Mode::Normal,
 									// TODO: handle and display connection errors.
 									ServerHandle::new(
 										stream,
-										ClientAuthentication { username },
-										client_routing,
+										ClientAuthentication { username, password },
+										client_routing.map_or(
+											server_handle::Destination::Lobby,
+											server_handle::Destination::Route,
+										),
 										&lua,
 										texture::Manager::new(&texture_creator),
 									)
@@ -201,6 +209,27 @@ pub(crate) async fn main() {
 								menu = None;
 							}
 						}
+					} else if let Some((_, world_state)) = &server
+						&& (world_state.summary.is_some() || world_state.rejection.is_some())
+					{
+						// Once the run has ended (or never started), confirming returns to the login menu.
+						if let Event::KeyDown {
+							keycode: Some(keycode),
+							..
+						} = event && options.controls.confirm.contains(keycode)
+						{
+							server = None;
+							if let Some(internal_server) = internal_server.take() {
+								internal_server.stop().await;
+							}
+							menu = Some(Box::new(menu::login::State::new(
+								cli.username.as_deref(),
+								None,
+								texture_creator
+									.load_texture_bytes(include_bytes!("res/missing_texture.png"))
+									.expect("missing texture should not fail to load"),
+							)));
+						}
 					} else if let Some((mut input_mode, mut world_state)) = server {
 						input_mode = world_state
 							.event(input_mode, event, &lua, &options)
@@ -224,12 +253,7 @@ pub(crate) async fn main() {
 
 			if let Some((input_mode, server)) = &mut server {
 				server.tick(delta, input_mode).await.unwrap();
-				if let Some(world) = &mut server.world {
-					// TODO: Avoid ticking more than once when too late in the frame.
-					world
-						.tick(&server.resources, &lua, &server.console)
-						.unwrap();
-				}
+				server.tick_world(&lua).await.unwrap();
 			}
 		}
 
@@ -255,9 +279,7 @@ pub(crate) async fn main() {
 	}
 
 	if let Some(internal_server) = internal_server {
-		// TODO: join the internal server thread.
-		drop(internal_server.instance);
-		internal_server.router.abort();
+		internal_server.stop().await;
 	}
 
 	exit(0);
@@ -266,20 +288,44 @@ pub(crate) async fn main() {
 struct InternalServer {
 	address: SocketAddr,
 	router: task::JoinHandle<()>,
-	instance: thread::JoinHandle<anyhow::Result<()>>,
+	instance: thread::JoinHandle<anyhow::Result<Option<replay::Replay>>>,
+	shutdown: watch::Sender<bool>,
 }
 
 impl InternalServer {
+	async fn stop(self) {
+		self.shutdown.send_replace(true);
+		if let Err(msg) = self.router.await {
+			error!("internal router task failed: {msg}");
+		}
+		// Unfinished singleplayer runs aren't kept, so the instance's result is only checked for panics.
+		// (errors are logged by the thread itself)
+		match task::spawn_blocking(move || self.instance.join()).await {
+			Ok(Ok(_)) => {}
+			Ok(Err(_)) => error!("server instance panicked"),
+			Err(msg) => error!("failed to join server instance: {msg}"),
+		}
+	}
+
 	async fn new() -> Result<InternalServer, rancor::BoxedError> {
 		let listener = TcpListener::bind((Ipv4Addr::new(127, 0, 0, 1), protocol::DEFAULT_PORT))
 			.await
 			.into_trace("while binding TCP listener")?;
 		let address = listener.local_addr().expect("missing local addr");
 		let (router, reciever) = mpsc::channel(4);
+		let (shutdown, mut shutdown_reciever) = watch::channel(false);
+		let instance_shutdown = shutdown.subscribe();
 		let instance = thread::Builder::new()
 			.name(String::from("instance"))
 			.spawn(move || {
-				let result = esprit2_server::instance(reciever, options::resource_directory());
+				let result = esprit2_server::instance(
+					reciever,
+					options::resource_directory(),
+					Some(options::user_directory().join("replays")),
+					Default::default(),
+					None,
+					instance_shutdown,
+				);
 				if let Err(e) = &result {
 					error!("server instance returned an error: {e}");
 				}
@@ -289,7 +335,11 @@ impl InternalServer {
 		let router = task::spawn(
 			async move {
 				loop {
-					match listener.accept().await {
+					let stream = select! {
+						stream = listener.accept() => stream,
+						_ = shutdown_reciever.wait_for(|x| *x) => break,
+					};
+					match stream {
 						// No routing necessary, just forward all streams to the instance.
 						Ok((stream, peer_addr)) => {
 							info!(peer = peer_addr.to_string(), "connected");
@@ -314,6 +364,7 @@ impl InternalServer {
 			address,
 			router,
 			instance,
+			shutdown,
 		})
 	}
 }
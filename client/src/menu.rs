@@ -138,3 +138,164 @@ pub(crate) mod login {
 		}
 	}
 }
+
+pub(crate) mod lobby {
+	use sdl3::event::Event;
+	use sdl3::render::Texture;
+
+	use super::Menu;
+	use crate::input::{LineInput, Signal};
+	use crate::prelude::*;
+
+	pub(crate) enum Response {
+		Join {
+			instance: protocol::InstanceTarget,
+			password: Option<Box<str>>,
+			spectate: bool,
+		},
+		Refresh,
+	}
+
+	enum Prompt {
+		Password(u32),
+		Name,
+	}
+
+	/// A router's instance directory.
+	pub(crate) struct State<'texture> {
+		pub(crate) cursor: Texture<'texture>,
+
+		pub(crate) instances: Vec<protocol::InstanceInfo>,
+		/// Indexes `instances`, followed by the "create" and "refresh" entries.
+		pub(crate) selection: usize,
+		/// Why the last attempt to join an instance failed.
+		pub(crate) error: Option<Box<str>>,
+		/// Join existing instances as a spectator rather than a player.
+		pub(crate) spectate: bool,
+		prompt: Option<(Prompt, LineInput)>,
+	}
+
+	impl<'texture> State<'texture> {
+		pub(crate) fn new(cursor: Texture<'texture>) -> Self {
+			Self {
+				cursor,
+				instances: Vec::new(),
+				selection: 0,
+				error: None,
+				spectate: false,
+				prompt: None,
+			}
+		}
+
+		pub(crate) fn set_instances(&mut self, instances: Vec<protocol::InstanceInfo>) {
+			self.instances = instances;
+			// Keep the cursor on the same kind of entry if the list changed size.
+			self.selection = self.selection.min(self.instances.len() + 1);
+		}
+	}
+
+	impl Menu<Response> for State<'_> {
+		fn event(&mut self, event: &Event, options: &Options) -> Signal<Response> {
+			if let Some((prompt, input)) = &mut self.prompt {
+				match input.dispatch(event, options, |line| Signal::Yield(Box::<str>::from(line))) {
+					Signal::Yield(line) => {
+						let response = match prompt {
+							Prompt::Password(id) => Response::Join {
+								instance: protocol::InstanceTarget::Id(*id),
+								password: Some(line),
+								spectate: self.spectate,
+							},
+							// Spectating can't create an instance, so new ones are always joined as a player.
+							Prompt::Name => Response::Join {
+								instance: protocol::InstanceTarget::Name(line),
+								password: None,
+								spectate: false,
+							},
+						};
+						self.prompt = None;
+						return Signal::Yield(response);
+					}
+					Signal::Cancel => self.prompt = None,
+					Signal::None => {}
+				}
+				return Signal::None;
+			}
+
+			let Event::KeyDown {
+				keycode: Some(keycode),
+				..
+			} = event
+			else {
+				return Signal::None;
+			};
+			let entries = self.instances.len() + 2;
+			if options.controls.down.contains(*keycode) {
+				self.selection = (self.selection + 1) % entries;
+			} else if options.controls.up.contains(*keycode) {
+				self.selection = (self.selection + entries - 1) % entries;
+			} else if options.controls.left.contains(*keycode)
+				|| options.controls.right.contains(*keycode)
+			{
+				self.spectate = !self.spectate;
+			} else if options.controls.confirm.contains(*keycode) {
+				match self.instances.get(self.selection) {
+					Some(instance) if instance.password => {
+						self.prompt = Some((Prompt::Password(instance.id), LineInput::default()));
+					}
+					Some(instance) => {
+						return Signal::Yield(Response::Join {
+							instance: protocol::InstanceTarget::Id(instance.id),
+							password: None,
+							spectate: self.spectate,
+						});
+					}
+					None if self.selection == self.instances.len() => {
+						self.prompt = Some((Prompt::Name, LineInput::default()));
+					}
+					None => return Signal::Yield(Response::Refresh),
+				}
+			}
+			Signal::None
+		}
+
+		fn draw(&self, gui: &mut gui::Context) {
+			gui.label(if self.spectate {
+				"Spectate an instance: (left/right to play instead)"
+			} else {
+				"Join an instance: (left/right to spectate instead)"
+			});
+			if let Some(error) = &self.error {
+				gui.label(error);
+			}
+			let instances = self
+				.instances
+				.iter()
+				.map(|x| {
+					format!(
+						"{} ({} playing){}",
+						x.name,
+						x.players,
+						if x.password { " [password]" } else { "" }
+					)
+				})
+				.collect::<Vec<_>>();
+			gui.menu(
+				Some((self.selection, &self.cursor)),
+				instances
+					.iter()
+					.map(String::as_str)
+					.chain(["Create a new instance", "Refresh"]),
+			);
+			if let Some((prompt, input)) = &self.prompt {
+				gui.horizontal();
+				gui.advance(10, 0);
+				gui.label(match prompt {
+					Prompt::Password(_) => "Password: ",
+					Prompt::Name => "Name: ",
+				});
+				gui.label(input);
+				gui.vertical();
+			}
+		}
+	}
+}
@@ -100,6 +100,20 @@ pub(crate) struct ConsoleColors {
 	pub(crate) combat: Color,
 }
 
+impl ConsoleColors {
+	pub(crate) fn get(&self, color: &console::Color) -> Color {
+		match color {
+			console::Color::Normal => self.normal,
+			console::Color::System => self.system,
+			console::Color::Unimportant => self.unimportant,
+			console::Color::Defeat => self.defeat,
+			console::Color::Danger => self.danger,
+			console::Color::Important => self.important,
+			console::Color::Special => self.special,
+		}
+	}
+}
+
 impl Default for ConsoleColors {
 	fn default() -> Self {
 		Self {
@@ -1,21 +1,182 @@
+use std::fs;
 use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
 
+use crate::menu::Menu;
 use crate::prelude::*;
 use esprit2::prelude::*;
 use protocol::{
-	ClientAuthentication, ClientIdentifier, ClientPacket, PacketReceiver, PacketSender,
+	ClientAuthentication, ClientHandshake, ClientIdentifier, ClientPacket, PacketReceiver,
+	PacketSender,
 };
 use rkyv::util::AlignedVec;
 use sdl3::rect::Rect;
 use tokio::net::TcpStream;
 
+const UNREADABLE_PACKET: &str =
+	"Received a packet that couldn't be read. The server may be running a different version.";
+const SHUTTING_DOWN: &str =
+	"The server is shutting down. If it saves runs, this one can be rejoined once it's back.";
+const TIMED_OUT: &str = "The server stopped responding.";
+
+/// Where modules sent by servers are saved.
+///
+/// Each module is stored under its version,
+/// so that servers with different versions of the same module don't overwrite each other.
+fn module_cache() -> PathBuf {
+	options::user_directory().join("modules")
+}
+
+fn cached_module_path(module: &replay::Module) -> PathBuf {
+	module_cache()
+		.join(format!("{:016x}", module.version))
+		.join(&*module.name)
+}
+
+fn cached_modules() -> Vec<Box<Path>> {
+	let Ok(versions) = module_cache().read_dir() else {
+		return Vec::new();
+	};
+	versions
+		.filter_map(|x| x.ok()?.path().read_dir().ok())
+		.flatten()
+		.filter_map(|x| Some(x.ok()?.path().into_boxed_path()))
+		.filter(|x| x.is_dir())
+		.collect()
+}
+
+/// Returns true if `path` can't escape the directory it is joined to.
+fn is_contained(path: &Path) -> bool {
+	path.components()
+		.all(|x| matches!(x, std::path::Component::Normal(_)))
+}
+
+/// Waits for the server to accept the handshake, saving any module files it sends along the way.
+///
+/// Returns the paths of the modules the server asked for,
+/// or the reason the server turned the client away.
+async fn receive_modules(
+	receiver: &mut mpsc::Receiver<AlignedVec>,
+	available: &[(Box<Path>, replay::Module)],
+) -> anyhow::Result<Result<Vec<Box<Path>>, Box<str>>> {
+	use anyhow::Context;
+
+	let mut received = Vec::new();
+	loop {
+		let Some(packet) = receiver.recv().await else {
+			anyhow::bail!("server closed the connection during the handshake");
+		};
+		let packet = match rkyv::access::<_, rancor::BoxedError>(&packet) {
+			Ok(packet) => packet,
+			Err(msg) => {
+				error!("failed to read packet from server: {msg}");
+				return Ok(Err(UNREADABLE_PACKET.into()));
+			}
+		};
+		match packet {
+			protocol::ArchivedServerPacket::Rejected(error) => {
+				let error: protocol::HandshakeError =
+					rkyv::deserialize::<_, rancor::BoxedError>(error)
+						.context("failed to deserialize rejection packet")?;
+				error!("server rejected connection: {error}");
+				return Ok(Err(error.to_string().into()));
+			}
+			protocol::ArchivedServerPacket::ModuleFile {
+				module,
+				path,
+				contents,
+			} => {
+				let module: replay::Module = rkyv::deserialize::<_, rancor::BoxedError>(module)
+					.context("failed to deserialize module file packet")?;
+				let path = Path::new(&**path);
+				if !is_contained(Path::new(&*module.name)) || !is_contained(path) {
+					anyhow::bail!("server sent a module file outside of the module cache");
+				}
+				let directory = cached_module_path(&module);
+				if !received.contains(&module) {
+					info!(module = module.name, "receiving module");
+					// Discard anything left over from an interrupted transfer.
+					let _ = fs::remove_dir_all(&directory);
+					received.push(module);
+				}
+				let path = directory.join(path);
+				if let Some(parent) = path.parent() {
+					fs::create_dir_all(parent).context("failed to create module directory")?;
+				}
+				fs::write(&path, contents.as_slice()).context("failed to write module file")?;
+			}
+			protocol::ArchivedServerPacket::Accepted { modules } => {
+				let modules: Vec<replay::Module> =
+					rkyv::deserialize::<_, rancor::BoxedError>(modules)
+						.context("failed to deserialize accepted packet")?;
+				return modules
+					.iter()
+					.map(|module| {
+						if let Some((path, _)) = available.iter().find(|(_, x)| x == module) {
+							return Ok(path.clone());
+						}
+						let path = cached_module_path(module);
+						if replay::Module::open(&path).ok().as_ref() != Some(module) {
+							anyhow::bail!("module {} was not received correctly", module.name);
+						}
+						Ok(path.into_boxed_path())
+					})
+					.collect::<anyhow::Result<_>>()
+					.map(Ok);
+			}
+			_ => warn!("ignoring unexpected packet during handshake"),
+		}
+	}
+}
+
+struct Acknowledgement {
+	sequence: u32,
+	checksum: protocol::Checksum,
+	messages: Vec<console::Message>,
+}
+
+/// What to ask the server for once connected.
+pub(crate) enum Destination {
+	/// Start a new instance.
+	New,
+	/// Join a specific instance.
+	Route(ClientRouting),
+	/// Browse the router's instances before picking one.
+	Lobby,
+}
+
 pub(crate) struct ServerHandle<'texture> {
 	sender: PacketSender,
 	_internal_receiver: PacketReceiver,
 	receiver: mpsc::Receiver<AlignedVec>,
 	identifier: Option<ClientIdentifier>,
+	/// Sequence number of the most recently sent action.
+	sequence: u32,
+	/// The server's response to the most recently sent action,
+	/// held until the predicted world catches up to it.
+	acknowledgement: Option<Acknowledgement>,
+	/// Set after a misprediction, until the server sends a fresh copy of the world.
+	resyncing: bool,
+	/// When the server was last pinged.
+	last_ping: Instant,
+	/// When the server last sent a packet.
+	last_packet: Instant,
 
 	pub(crate) world: Option<world::Manager>,
+	/// Present once the server has declared the run over.
+	pub(crate) summary: Option<world::Summary>,
+	/// Present if the server turned the client away, or sent a packet the client couldn't read.
+	pub(crate) rejection: Option<Box<str>>,
+	/// Present until the client joins an instance, if it was asked to browse for one.
+	pub(crate) lobby: Option<menu::lobby::State<'texture>>,
+	/// Spectators watch without acting, and their camera follows a party member of their choice.
+	pub(crate) spectating: bool,
+	/// Index of the party member followed by spectators.
+	pub(crate) followed: usize,
+	/// Present while the player is typing a chat message.
+	pub(crate) chat: Option<input::LineInput>,
+	/// Round-trip time of the most recently answered ping.
+	pub(crate) latency: Option<Duration>,
 	pub(crate) resources: resource::Handle,
 	pub(crate) textures: texture::Manager<'texture>,
 	pub(crate) console: Console,
@@ -29,15 +190,16 @@ impl<'texture> ServerHandle<'texture> {
 	pub(crate) async fn new<'lua>(
 		stream: TcpStream,
 		authentication: ClientAuthentication,
-		routing: Option<ClientRouting>,
+		destination: Destination,
 		lua: &'lua mlua::Lua,
 		mut texture_manager: texture::Manager<'texture>,
 	) -> anyhow::Result<Self> {
 		// Create a console.
-		// (local messages generated by the world cache are discarded)
+		// Messages generated by predicted actions are printed immediately,
+		// and the server's versions are only shown if the prediction turns out to be wrong.
 		let console = Console::default();
 
-		let modules = anyhow::Context::context(
+		let local_modules = anyhow::Context::context(
 			options::resource_directory().read_dir(),
 			"failed to read contents of resource directory",
 		)?
@@ -49,7 +211,55 @@ impl<'texture> ServerHandle<'texture> {
 				None
 			}
 		})
-		.collect::<Box<[Box<Path>]>>();
+		.collect::<Vec<Box<Path>>>();
+		let mut available_modules = Vec::new();
+		for path in local_modules.iter().cloned().chain(cached_modules()) {
+			match replay::Module::open(&path) {
+				Ok(module) => available_modules.push((path, module)),
+				Err(msg) => warn!(path = %path.display(), "failed to read module: {msg}"),
+			}
+		}
+
+		let (receiver, sender) = stream.into_split();
+		let sender = PacketSender::new(sender);
+		sender
+			.send(&ClientPacket::Handshake(ClientHandshake::new(
+				available_modules
+					.iter()
+					.map(|(_, module)| module.clone())
+					.collect(),
+			)))
+			.await?;
+		sender
+			.send(&ClientPacket::Authenticate(authentication))
+			.await?;
+		let spectating = matches!(&destination, Destination::Route(routing) if routing.spectate);
+		let lobby = match destination {
+			Destination::New => {
+				sender.send(&ClientPacket::Instantiate).await?;
+				None
+			}
+			Destination::Route(routing) => {
+				sender.send(&ClientPacket::Route(routing)).await?;
+				None
+			}
+			Destination::Lobby => {
+				sender.send(&ClientPacket::ListInstances).await?;
+				Some(menu::lobby::State::new(
+					texture_manager
+						.texture_creator
+						.load_texture_bytes(include_bytes!("res/missing_texture.png"))
+						.map_err(|msg| anyhow::anyhow!("failed to load cursor texture: {msg}"))?,
+				))
+			}
+		};
+		let (_internal_receiver, mut receiver) = PacketReceiver::new(receiver);
+		let (modules, rejection) = match receive_modules(&mut receiver, &available_modules).await? {
+			Ok(modules) => (modules, None),
+			// Resources are still needed to show the rejection, so fall back to the local ones.
+			Err(rejection) => (local_modules, Some(rejection)),
+		};
+
 		let (resources, errors) =
 			resource::open(lua, modules.iter().map(|x| x.as_ref()), |name, _, init| {
 				use mlua::ErrorContext;
@@ -117,9 +327,10 @@ impl<'texture> ServerHandle<'texture> {
 			"runtime.resources",
 			lua.create_function(move |_, ()| Ok(handle.clone()))?,
 		)?;
+		let handle = console.handle.clone();
 		lua.load_from_function::<mlua::Value>(
 			"runtime.console",
-			lua.create_function(move |_, ()| Ok(console::LuaHandle(console_impl::Dummy)))?,
+			lua.create_function(move |_, ()| Ok(console::LuaHandle(handle.clone())))?,
 		)?;
 		// input requests need to yield so this library is written in lua.
 		let make_cursor = mlua::Function::wrap(|x, y, range, radius| {
@@ -154,25 +365,25 @@ impl<'texture> ServerHandle<'texture> {
 			.into_function()?,
 		)?;
 
-		let (receiver, sender) = stream.into_split();
-		let sender = PacketSender::new(sender);
-		sender
-			.send(&ClientPacket::Authenticate(authentication))
-			.await?;
-		if let Some(routing) = routing {
-			sender.send(&ClientPacket::Route(routing)).await?;
-		} else {
-			sender.send(&ClientPacket::Instantiate).await?;
-		}
-		let (_internal_receiver, receiver) = PacketReceiver::new(receiver);
-
 		Ok(Self {
 			sender,
 			_internal_receiver,
 			receiver,
 			identifier: None,
+			sequence: 0,
+			acknowledgement: None,
+			resyncing: false,
+			last_ping: Instant::now(),
+			last_packet: Instant::now(),
 
 			world: None,
+			summary: None,
+			rejection,
+			lobby,
+			spectating,
+			followed: 0,
+			chat: None,
+			latency: None,
 			resources,
 			textures: texture_manager,
 			console,
@@ -192,14 +403,57 @@ impl<'texture> ServerHandle<'texture> {
 
 		let world = self.world.as_mut().expect("world must be present");
 		world
-			.perform_action(console_impl::Dummy, &self.resources, lua, action.clone())
+			.perform_action(&self.console, &self.resources, lua, action.clone())
 			.context("failed to perform action")?;
+		self.sequence = self.sequence.wrapping_add(1);
 		self.sender
-			.send(&protocol::ClientPacket::Action { action })
+			.send(&protocol::ClientPacket::Action {
+				sequence: self.sequence,
+				action,
+			})
 			.await
 			.context("failed to serialize action packet")
 	}
 
+	/// Performs the predicted world's next automatic action.
+	///
+	/// Once the world is waiting on input again, it is compared against the server's,
+	/// and a fresh copy is requested if they differ.
+	pub(crate) async fn tick_world(&mut self, lua: &mlua::Lua) -> anyhow::Result<()> {
+		use anyhow::Context;
+
+		let Some(world) = &mut self.world else {
+			return Ok(());
+		};
+		if self.resyncing {
+			return Ok(());
+		}
+		// TODO: Avoid ticking more than once when too late in the frame.
+		if world.tick(&self.resources, lua, &self.console)? {
+			return Ok(());
+		}
+		let Some(acknowledgement) = self.acknowledgement.take() else {
+			return Ok(());
+		};
+		if protocol::world_checksum(world) != acknowledgement.checksum {
+			warn!(
+				sequence = acknowledgement.sequence,
+				"predicted world differs from the server's; resynchronizing"
+			);
+			self.console
+				.print_system("Lost sync with the server. Here's what really happened:");
+			for message in acknowledgement.messages {
+				self.console.send_message(message);
+			}
+			self.resyncing = true;
+			self.sender
+				.send(&ClientPacket::RequestWorld)
+				.await
+				.context("failed to serialize world request packet")?;
+		}
+		Ok(())
+	}
+
 	pub(crate) async fn event(
 		&mut self,
 		input_mode: input::Mode,
@@ -207,6 +461,69 @@ impl<'texture> ServerHandle<'texture> {
 		lua: &mlua::Lua,
 		options: &Options,
 	) -> anyhow::Result<input::Mode> {
+		use anyhow::Context;
+
+		if let Some(lobby) = &mut self.lobby
+			&& self.rejection.is_none()
+		{
+			match lobby.event(&event, options) {
+				input::Signal::Yield(menu::lobby::Response::Join {
+					instance,
+					password,
+					spectate,
+				}) => {
+					lobby.error = None;
+					self.spectating = spectate;
+					self.sender
+						.send(&ClientPacket::Route(ClientRouting {
+							instance,
+							instance_password: password,
+							spectate,
+						}))
+						.await
+						.context("failed to serialize routing packet")?;
+				}
+				input::Signal::Yield(menu::lobby::Response::Refresh) => {
+					self.sender
+						.send(&ClientPacket::ListInstances)
+						.await
+						.context("failed to serialize instance list request")?;
+				}
+				input::Signal::None | input::Signal::Cancel => {}
+			}
+			return Ok(input_mode);
+		}
+		if let Some(chat) = &mut self.chat {
+			match chat.dispatch(&event, options, |line| {
+				input::Signal::Yield(Box::<str>::from(line.trim()))
+			}) {
+				input::Signal::Yield(text) => {
+					self.chat = None;
+					if !text.is_empty() {
+						self.sender
+							.send(&ClientPacket::Chat(text))
+							.await
+							.context("failed to serialize chat packet")?;
+					}
+				}
+				input::Signal::Cancel => self.chat = None,
+				input::Signal::None => {}
+			}
+			return Ok(input_mode);
+		}
+		// Opening chat once the key is released keeps it from being typed into the message.
+		if let sdl3::event::Event::KeyUp {
+			keycode: Some(keycode),
+			..
+		} = event && options.controls.talk.contains(keycode)
+			&& matches!(input_mode, input::Mode::Normal)
+			&& self.world.is_some()
+			&& self.summary.is_none()
+			&& self.rejection.is_none()
+		{
+			self.chat = Some(input::LineInput::default());
+			return Ok(input_mode);
+		}
 		let sdl3::event::Event::KeyDown {
 			keycode: Some(keycode),
 			..
@@ -217,6 +534,18 @@ impl<'texture> ServerHandle<'texture> {
 		let Some(world) = &self.world else {
 			return Ok(input_mode);
 		};
+		if self.summary.is_some() || self.rejection.is_some() || self.resyncing {
+			return Ok(input_mode);
+		}
+		if self.spectating {
+			let party = world.party.len().max(1);
+			if options.controls.left.contains(keycode) {
+				self.followed = (self.followed + party - 1) % party;
+			} else if options.controls.right.contains(keycode) {
+				self.followed = (self.followed + 1) % party;
+			}
+			return Ok(input_mode);
+		}
 
 		if !world
 			.next_character()
@@ -286,10 +615,64 @@ impl<'texture> ServerHandle<'texture> {
 		input_mode: &mut input::Mode,
 	) -> Result<(), rancor::BoxedError> {
 		while let Ok(packet) = self.receiver.try_recv() {
-			let packet = rkyv::access(&packet)?;
+			self.last_packet = Instant::now();
+			if self.rejection.is_some() {
+				continue;
+			}
+			let packet = match rkyv::access::<_, rancor::BoxedError>(&packet) {
+				Ok(packet) => packet,
+				Err(msg) => {
+					error!("failed to read packet from server: {msg}");
+					self.rejection = Some(UNREADABLE_PACKET.into());
+					continue;
+				}
+			};
 			match packet {
+				protocol::ArchivedServerPacket::Rejected(error) => {
+					let error: protocol::HandshakeError =
+						rkyv::deserialize(error).trace("while deserializing rejection packet")?;
+					error!("server rejected connection: {error}");
+					self.rejection = Some(error.to_string().into());
+				}
+				protocol::ArchivedServerPacket::AuthenticationFailed(error) => {
+					let error: protocol::AuthenticationError = rkyv::deserialize(error)
+						.trace("while deserializing authentication packet")?;
+					error!("server denied access: {error}");
+					// A wrong instance password shouldn't cost the player their place in the lobby.
+					if let Some(lobby) = &mut self.lobby {
+						lobby.error = Some(error.to_string().into());
+					} else {
+						self.rejection = Some(error.to_string().into());
+					}
+				}
+				protocol::ArchivedServerPacket::RoutingFailed(error) => {
+					let error: protocol::RoutingError =
+						rkyv::deserialize(error).trace("while deserializing routing packet")?;
+					error!("server could not route client: {error}");
+					if let Some(lobby) = &mut self.lobby {
+						lobby.error = Some(error.to_string().into());
+					} else {
+						self.rejection = Some(error.to_string().into());
+					}
+				}
+				protocol::ArchivedServerPacket::Instances(instances) => {
+					if let Some(lobby) = &mut self.lobby {
+						lobby.set_instances(
+							rkyv::deserialize(instances)
+								.trace("while deserializing instance list packet")?,
+						);
+					}
+				}
+				// Only expected during the handshake.
+				protocol::ArchivedServerPacket::ModuleFile { .. }
+				| protocol::ArchivedServerPacket::Accepted { .. } => {}
 				protocol::ArchivedServerPacket::Ping => {
-					// TODO: Respond to pings
+					if let Err(msg) = self.sender.send(&ClientPacket::Pong).await {
+						error!("failed to answer ping: {msg}");
+					}
+				}
+				protocol::ArchivedServerPacket::Pong => {
+					self.latency = Some(self.last_ping.elapsed());
 				}
 				protocol::ArchivedServerPacket::Register(identifier) => {
 					self.identifier = Some(identifier.to_native());
@@ -297,12 +680,51 @@ impl<'texture> ServerHandle<'texture> {
 				protocol::ArchivedServerPacket::World { world } => {
 					self.world =
 						Some(rkyv::deserialize(world).trace("while deserializing world packet")?);
+					self.acknowledgement = None;
+					self.resyncing = false;
+					self.lobby = None;
 				}
 				protocol::ArchivedServerPacket::Message(message) => {
 					self.console.history.push(
 						rkyv::deserialize(message).trace("while deserializing message packet")?,
 					);
 				}
+				protocol::ArchivedServerPacket::Acknowledge {
+					sequence,
+					checksum,
+					messages,
+				} => {
+					// Acknowledgements for older actions can't be compared against the current prediction.
+					if sequence.to_native() == self.sequence {
+						self.acknowledgement = Some(Acknowledgement {
+							sequence: sequence.to_native(),
+							checksum: checksum.to_native(),
+							messages: rkyv::deserialize(messages)
+								.trace("while deserializing acknowledgement packet")?,
+						});
+					}
+				}
+				protocol::ArchivedServerPacket::ShuttingDown => {
+					info!("server is shutting down");
+					self.rejection = Some(SHUTTING_DOWN.into());
+				}
+				protocol::ArchivedServerPacket::RunEnded(summary) => {
+					self.summary = Some(
+						rkyv::deserialize(summary).trace("while deserializing summary packet")?,
+					);
+				}
+			}
+		}
+
+		if self.rejection.is_none() {
+			if self.last_packet.elapsed() > protocol::TIMEOUT {
+				error!("server stopped responding");
+				self.rejection = Some(TIMED_OUT.into());
+			} else if self.last_ping.elapsed() >= protocol::HEARTBEAT_INTERVAL {
+				self.last_ping = Instant::now();
+				if let Err(msg) = self.sender.send(&ClientPacket::Ping).await {
+					error!("failed to ping server: {msg}");
+				}
 			}
 		}
 
@@ -327,17 +749,46 @@ impl<'texture> ServerHandle<'texture> {
 		lua: &mlua::Lua,
 		options: &Options,
 	) {
+		if let Some(rejection) = &self.rejection {
+			ctx.label("The server rejected this client:");
+			ctx.label(rejection);
+			ctx.advance(0, 20);
+			ctx.label("Press enter to return to the menu.");
+			return;
+		}
+		if let Some(lobby) = &self.lobby {
+			lobby.draw(ctx);
+			return;
+		}
+		if let Some(summary) = &self.summary {
+			ctx.label(match summary.outcome {
+				world::Outcome::Victory => "Victory!",
+				world::Outcome::Defeat => "Defeat...",
+			});
+			ctx.label(&format!("Reached floor {}", summary.floor + 1));
+			ctx.label(&format!("Defeated {} foes", summary.defeated));
+			for (name, experience) in &summary.party {
+				ctx.label(&format!("{name}: {experience} experience"));
+			}
+			ctx.advance(0, 20);
+			ctx.label("Press enter to return to the menu.");
+			return;
+		}
 		if let Some(world) = &self.world {
 			// Render World
 			let width = 480;
 			let height = 320;
 			let mut camera = draw::Camera::default();
 			camera.update_size(width, height);
-			if let Some(focused_character) = &world
-				.characters
-				.iter()
-				.find(|x| x.borrow().components.contains_key(":conscious"))
-			{
+			let focused_character = if self.spectating {
+				world.party.get(self.followed).map(|x| &x.piece)
+			} else {
+				world
+					.characters
+					.iter()
+					.find(|x| x.borrow().components.contains_key(":conscious"))
+			};
+			if let Some(focused_character) = focused_character {
 				if let input::Mode::Cursor(input::Cursor { position, .. }) = &input_mode {
 					camera.focus_character_with_cursor(&focused_character.borrow(), *position);
 				} else {
@@ -382,6 +833,13 @@ impl<'texture> ServerHandle<'texture> {
 			// Render User Interface
 			ctx.canvas.set_viewport(None);
 
+			if let Some(latency) = self.latency {
+				ctx.label(&format!("Ping: {}ms", latency.as_millis()));
+			}
+			if self.spectating {
+				ctx.label("Spectating (left/right to follow another party member)");
+			}
+
 			let mut menu = ctx.view(
 				0,
 				(ctx.rect.height() - options.ui.console_height) as i32,
@@ -398,6 +856,12 @@ impl<'texture> ServerHandle<'texture> {
 				&self.resources,
 				&self.textures,
 			);
+			if let Some(chat) = &self.chat {
+				menu.horizontal();
+				menu.label("Say: ");
+				menu.label(chat);
+				menu.vertical();
+			}
 
 			// Draw pamphlet
 			let mut pamphlet_ctx = ctx.view(
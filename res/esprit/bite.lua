@@ -10,9 +10,7 @@ resources.ability "bite" {
 
 	on_use = function(user, _, args)
 		local combat = require "engine.combat"
-		local console = require "runtime.console"
 		local world = require "engine.world"
-		local log = require "engine.types.log"
 
 		local target = world.character_at(args.target.x, args.target.y)
 		if target == nil then return end
@@ -20,14 +18,9 @@ resources.ability "bite" {
 		-- TODO: see scratch.lua for info
 		-- if combat.alliance_check(User, target) and not combat.alliance_prompt() then return end
 
-		-- Bite has high damage, but also a relatively high pierce threshold for a melee attack.
-		local damage, pierce_failed = combat.apply_pierce(4, magnitude(user) - target.stats.defense)
-
 		-- Biting requires you to get closer to the enemy, lowering your physical defense.
 		user:attach("esprit:close_combat")
 
-		target.hp = target.hp - damage
-
 		local damage_messages = {
 			"{self_Address} bites {target_address}",
 			"{self_Address} bites into {target_address}",
@@ -47,13 +40,21 @@ resources.ability "bite" {
 			return combat.format(user, target, table[math.random(#table)])
 		end
 
-		if pierce_failed then
-			console:combat_log(pick(glance_messages), log.Glance)
-		elseif damage == 0 then
-			console:combat_log(pick(failure_messages), log.Miss)
-		else
-			console:combat_log(pick(damage_messages), log.Hit(damage))
-		end
+		local attack = {
+			magnitude = magnitude(user),
+			kind = "physical",
+			-- Bite has high damage, but also a relatively high pierce threshold for a melee attack.
+			pierce = 4,
+		}
+		combat.damage(user, target, attack, function(log)
+			if log:glance() then
+				return pick(glance_messages)
+			elseif log:miss() then
+				return pick(failure_messages)
+			else
+				return pick(damage_messages)
+			end
+		end)
 
 		return use_time
 	end,
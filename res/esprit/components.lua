@@ -1,28 +1,23 @@
 local resources = require "std:resources"
+local duration = require "engine.types.duration"
 local stats = require "engine.types.stats"
 
-resources.component "level" {
-	name = "Level",
-}
-
-resources.component "experience" {
-	name = "Experience",
-}
-
-resources.component "skill/major" {
+resources.component "major" {
 	name = "Major Skill",
+	schema = "string",
 }
 
-resources.component "skill/minor" {
+resources.component "minor" {
 	name = "Minor Skill",
+	schema = "string",
 }
 
 resources.component "bleed" {
 	name = "Bleeding",
 	visible = true,
+	schema = "integer",
+	duration = duration.rest,
 
-	---@param user Piece
-	on_rest = function(user) user:detach("esprit:bleed") end,
 	---@param magnitude integer
 	---@return Stats
 	on_debuff = function(magnitude)
@@ -39,8 +34,8 @@ resources.component "bleed" {
 resources.component "close_combat" {
 	name = "Close Combat",
 	visible = true,
+	schema = "unit",
+	duration = duration.turn,
 
-	---@param user Piece
-	on_turn = function(user) user:detach("esprit:close_combat") end,
 	on_debuff = function() return stats.defense(4) end
 }
@@ -74,26 +74,19 @@ resources.ability "crush" {
 						character.x = projected_x
 						character.y = projected_y
 					else
-						local damage, pierce_failed = combat.apply_pierce(
-							pierce_threshold,
-							affinity:magnitude(user, magnitude(user)) +
-							distance_traveled * 2 -
-							character.stats.resistance
-						)
-
-						-- TODO Make messages vary based on distance travelled.
-						if damage > 0 then
-							character.hp = character.hp - damage
-							console:combat_log(
-								character:replace_nouns(damage_messages[math.random(#damage_messages)]),
-								log.Hit(damage)
-							)
-						else
-							console:combat_log(
-								character:replace_nouns(failure_messages[math.random(#failure_messages)]),
-								pierce_failed and log.Glance or log.Miss
-							)
-						end
+						local attack = {
+							magnitude = affinity:magnitude(user, magnitude(user)) + distance_traveled * 2,
+							kind = "magical",
+							pierce = pierce_threshold,
+						}
+						combat.damage(user, character, attack, function(log)
+							-- TODO Make messages vary based on distance travelled.
+							if log:hit() then
+								return character:replace_nouns(damage_messages[math.random(#damage_messages)])
+							else
+								return character:replace_nouns(failure_messages[math.random(#failure_messages)])
+							end
+						end)
 
 						-- Skip printing a neutral message
 						goto printed
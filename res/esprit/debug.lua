@@ -14,9 +14,11 @@ resources.ability "debug/level_up" {
 		local console = require "runtime.console"
 		local target = world.character_at(args.target.x, args.target.y)
 		if target == nil then return end
-		local level = (target:component("esprit:level") or 0) + 1
-		target:attach("esprit:level", level);
-		console:print(target:replace_nouns("{Address}'s level increased to " .. level))
+		if target:level_up() then
+			console:print(target:replace_nouns("{Address}'s level increased to " .. target.level))
+		else
+			console:print(target:replace_nouns("{Address} can't grow any further"))
+		end
 	end,
 	on_input = function(user)
 		local input = require "runtime.input"
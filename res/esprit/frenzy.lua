@@ -1,5 +1,7 @@
 local world = require "engine.world"
 local resources = require "std:resources"
+local duration = require "engine.types.duration"
+local stats = require "engine.types.stats"
 
 resources.ability "debug/frenzy" {
 	name = "Frenzy",
@@ -11,7 +13,7 @@ resources.ability "debug/frenzy" {
 		local target = world.character_at(args.target.x, args.target.y)
 		if target == nil then return end
 		console:print(target:replace_nouns("{Address} has been frenzied!"))
-		target:attach("esprit:frenzy", 2 * 12)
+		target:attach("esprit:frenzy", {}, duration.turns(2))
 	end,
 	on_input = function(user)
 		local input = require "runtime.input"
@@ -21,45 +23,34 @@ resources.ability "debug/frenzy" {
 	end,
 }
 
----@class Frenzy
----@field time_left number
----@field former_teams string[]
-
 resources.component "frenzy" {
 	name = "Frenzied",
 	visible = true,
+	-- Teams the piece belonged to before it was frenzied.
+	schema = { list = "string" },
 
 	---@param user Piece
-	---@param previous number|Frenzy?
+	---@param previous string[]?
 	on_attach = function(user, previous)
 		if previous == nil then
-			user:attach("esprit:frenzy", {
-				time_left = user:component("esprit:frenzy"),
-				former_teams = user:component("std:teams") or {},
-			} --[[@as Frenzy]])
+			user:attach("esprit:frenzy", user:component("std:teams") or {})
 			user:detach("std:teams")
 		end
 	end,
 	---@param user Piece
-	---@param previous Frenzy
+	---@param previous string[]
 	on_detach = function(user, previous)
 		-- Don't overwrite the current list, in case it changed.
-		for _, v in pairs(previous.former_teams) do
-			user:attach("std:teams", v)
+		local teams = user:component("std:teams") or {}
+		for _, v in ipairs(previous) do
+			table.insert(teams, v)
 		end
+		user:attach("std:teams", teams)
 	end,
 	---@param user Piece
-	---@param time number
-	on_turn = function(user, time)
+	on_expire = function(user)
 		local console = require "runtime.console"
-		---@type Frenzy
-		local frenzy = user:component("esprit:frenzy")
-		frenzy.time_left = frenzy.time_left - time
-		if frenzy.time_left <= 0 then
-			user:detach("esprit:frenzy")
-			console:print(user:replace_nouns("{Address} snapped out of {their} frenzy."))
-		else
-			user:attach("esprit:frenzy", frenzy)
-		end
-	end
+		console:print(user:replace_nouns("{Address} snapped out of {their} frenzy."))
+	end,
+	on_buff = function() return nil, stats.power(50) end,
 }
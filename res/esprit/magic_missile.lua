@@ -18,9 +18,7 @@ resources.ability "magic_missile" {
 	usable = ability.spell.make_castable(1, affinity),
 	on_use = function(user, _, args)
 		local combat = require "engine.combat"
-		local console = require "runtime.console"
 		local world = require "engine.world"
-		local log = require "engine.types.log"
 
 		local target = world.character_at(args.target.x, args.target.y)
 		if target == nil then return end
@@ -28,12 +26,6 @@ resources.ability "magic_missile" {
 		-- TODO: see scratch
 		-- if combat.alliance_check(User, target) and not combat.alliance_prompt() then return end
 
-		local damage, pierce_failed = combat.apply_pierce(
-			pierce_threshold,
-			math.max(0, affinity:magnitude(user, magnitude(user)) - target.stats.resistance)
-		)
-
-		target.hp = target.hp - damage
 		user.sp = user.sp - level
 
 		local damage_messages = {
@@ -66,19 +58,26 @@ resources.ability "magic_missile" {
 			return combat.format(user, target, table[math.random(#table)])
 		end
 
-		-- Avoid showing unskilled messages too often;
-		-- poorly made missiles are also likely to miss or be resisted.
-		if pierce_failed then
-			console:combat_log(pick(glancing_messages), log.Glance)
-		elseif damage == 0 then
-			if affinity:weak(user) and math.random(0, 1) == 1 then
-				console:combat_log(pick(unskilled_messages), log.Miss)
+		local attack = {
+			magnitude = affinity:magnitude(user, magnitude(user)),
+			kind = "magical",
+			pierce = pierce_threshold,
+		}
+		combat.damage(user, target, attack, function(log)
+			-- Avoid showing unskilled messages too often;
+			-- poorly made missiles are also likely to miss or be resisted.
+			if log:glance() then
+				return pick(glancing_messages)
+			elseif log:miss() then
+				if affinity:weak(user) and math.random(0, 1) == 1 then
+					return pick(unskilled_messages)
+				else
+					return pick(failure_messages)
+				end
 			else
-				console:combat_log(pick(failure_messages), log.Miss)
+				return pick(damage_messages)
 			end
-		else
-			console:combat_log(pick(damage_messages), log.Hit(damage))
-		end
+		end)
 
 		return cast_time
 	end,
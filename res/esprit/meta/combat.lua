@@ -10,12 +10,34 @@ local combat = {}
 ---@return string
 function combat.format(user, target, s) end
 
---- Adjust the magnitude based on the pierce threshold provided.
---- Returns whether or not the magnitude was adjusted.
---- @param pierce integer
---- @param magnitude integer
---- @return integer The resulting magnitude.
---- @return boolean Whether or not the magnitude was adjusted.
-function combat.apply_pierce(pierce, magnitude) end
+---@class DamageKind: userdata
+---@field physical fun(self): boolean
+---@field magical fun(self): boolean
+---@field pure fun(self): boolean
+
+---@alias DamageKindName "physical"|"magical"|"pure"
+
+---@class Attack
+---@field magnitude integer Negative magnitudes are treated as 0.
+---@field kind (DamageKind|DamageKindName)? Defaults to "physical".
+---@field pierce integer? Attacks which get past defenses by this much or less only glance off. Defaults to 0.
+
+--- Deal damage to a target, allowing both pieces' components to modify it first.
+--- Physical attacks are reduced by the target's defense, and magical attacks by its resistance.
+--- Health never drops below 0, and reaching 0 calls the target's on_death hooks.
+--- The outcome is sent to the combat log along with the text returned by `describe`.
+---@param user Piece
+---@param target Piece
+---@param attack Attack
+---@param describe fun(log: Log): string
+---@return integer The amount of health actually lost.
+---@return Log Hit if any health was lost, Glance if the attack failed to pierce, Miss otherwise.
+function combat.damage(user, target, attack, describe) end
+
+--- Restore health to a target, up to its maximum.
+---@param target Piece
+---@param amount integer
+---@return integer The amount of health actually restored.
+function combat.heal(target, amount) end
 
 return combat
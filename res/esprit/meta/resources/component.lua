@@ -1,12 +1,30 @@
 ---@meta init.resources.component
 
----@alias on_debuff fun(any): Stats
+---Returns flat modifiers, then optional percentage modifiers applied after every flat one.
+---@alias on_stat_modifier fun(any): Stats?, Stats?
+
+---@alias Schema "unit"|"boolean"|"integer"|"number"|"string"|{ list: Schema }|{ record: table<string, Schema> }
 
 ---@class ComponentTable
 ---@field name string
 ---@field icon string
----@field duration Duration
----@field on_debuff on_debuff?
+---@field visible boolean?
+---@field schema Schema? Values which don't match are rejected by `attach`.
+---@field duration Duration? How long the component lasts when `attach` isn't given a duration.
+---@field on_attach fun(user: Piece, previous: any)?
+---@field on_detach fun(user: Piece, previous: any, annotation: any)?
+---@field on_expire fun(user: Piece, value: any)? Called before the component is detached for running out of time.
+---@field on_turn fun(user: Piece, delay: integer)?
+---@field on_rest fun(user: Piece)?
+---@field on_attack fun(user: Piece, damage: integer, kind: DamageKind, target: Piece): integer?
+---@field on_defend fun(user: Piece, damage: integer, kind: DamageKind, attacker: Piece): integer?
+---@field on_damage fun(user: Piece, damage: integer, source: Piece)?
+---@field on_deal_damage fun(user: Piece, damage: integer, target: Piece)?
+---@field on_death fun(user: Piece, killer: Piece)?
+---@field on_move fun(user: Piece, from_x: integer, from_y: integer)?
+---@field on_floor_change fun(user: Piece, floor: integer)?
+---@field on_buff on_stat_modifier?
+---@field on_debuff on_stat_modifier?
 
 ---@param indentifier string
 ---@return fun(ComponentTable): Component
@@ -0,0 +1,28 @@
+---@meta init.resources.sheet
+
+---@class NounsTable
+---@field name string
+---@field proper_name boolean
+---@field pronouns string
+
+---@class GrowthTable
+---@field experience integer Total experience required to reach this level.
+---@field stats StatsTable?
+---@field abilities string[]?
+
+---@class SheetTable
+---@field nouns NounsTable
+---@field stats StatsTable
+---@field abilities string[]?
+---@field growth GrowthTable[]? The first entry is applied when reaching level 2.
+---@field components table<string, Value>? Components every piece starts with. `on_attach` is not called for these.
+---@field prototype fun(piece: Piece)? Called with each new piece after its initial components are attached.
+---@field experience integer?
+---@field corpse boolean?
+---@field on_consider string
+
+---@param indentifier string
+---@return fun(SheetTable)
+local function sheet(indentifier) end
+
+return sheet
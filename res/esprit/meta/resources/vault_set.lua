@@ -0,0 +1,20 @@
+---@meta init.resources.vault_set
+
+---@class VaultSetEntry
+---@field [1] string Identifier of the vault.
+---@field weight integer? How likely this vault is to be chosen. Defaults to 1.
+---@field min integer? How many times this vault must appear on a floor. Defaults to 0.
+---@field max integer? How many times this vault may appear on a floor.
+---@field depth [integer, integer]? Range of floors (inclusive) this vault may appear on.
+
+---@class VaultSetTable
+---@field density integer How many vaults to place on each floor.
+---@field hall_ratio number? Ratio of halls to vaults, from 0 to 1.
+---@field final_depth integer? The last floor; reaching its exit wins the run. Floors continue forever without one.
+---@field vaults (string|VaultSetEntry)[]
+
+---@param indentifier string
+---@return fun(VaultSetTable)
+local function vault_set(indentifier) end
+
+return vault_set
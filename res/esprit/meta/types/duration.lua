@@ -3,9 +3,17 @@
 ---@class Duration: userdata
 
 ---@class duration
----@field turn Duration
----@field rest Duration
----@field forever Duration
+---@field turn Duration Expires after one turn.
+---@field rest Duration Expires the next time the piece rests.
+---@field forever Duration Never expires on its own.
 local duration = {}
 
+---@param aut integer
+---@return Duration
+function duration.aut(aut) end
+
+---@param turns integer
+---@return Duration
+function duration.turns(turns) end
+
 return duration
@@ -1,6 +1,10 @@
 ---@meta engine.types.log
 
 ---@class (exact) Log: userdata
+---@field hit fun(self): boolean
+---@field success fun(self): boolean
+---@field miss fun(self): boolean
+---@field glance fun(self): boolean
 
 ---@class (exact) log: userdata
 ---@field Success Log
@@ -0,0 +1,11 @@
+---@meta engine.types.piece
+
+local piece = {}
+
+---Constructs a piece from a sheet, including its initial components and prototype.
+---The piece is not placed in the world.
+---@param sheet string
+---@return Piece
+function piece.new(sheet) end
+
+return piece
@@ -8,12 +8,16 @@
 ---@field y integer
 ---@field hp integer
 ---@field sp integer
+---@field level integer
+---@field experience integer
 ---@field stats Stats
 ---@field abilities fun(self): PieceNextAbility, self
 ---@field replace_nouns fun(self, s: string): string
----@field attach fun(self, key: string, value: any)
+---@field attach fun(self, key: string, value: any, duration: Duration|integer?)
 ---@field component fun(self, key: string): any
 ---@field detach fun(self, key: string)
+---@field level_up fun(self): boolean Applies the next level's growth, returning false if there is none.
+---@field gain_experience fun(self, amount: integer): integer Returns how many levels were gained.
 
 ---@class (exact) Ability: userdata
 ---@field on_consider string?
@@ -4,7 +4,6 @@ local world = require "engine.world"
 local action = require "engine.types.action"
 local consider = require "engine.types.consider"
 local heuristic = require "engine.types.heuristic"
-local log = require "engine.types.log"
 
 local resources = require "std:resources"
 local teams = require "std:teams"
@@ -17,26 +16,12 @@ resources.ability "scratch" {
 	description = "Causes a small amount of bleeding damage, which reduces defense.",
 
 	on_use = function(user, _, args)
-		local console = require "runtime.console"
-
 		local target = world.character_at(args.target.x, args.target.y)
 		if target == nil then return end
 
 		-- TODO: Since you can't request input in the middle of a script anymore, this needs to communicate a failure reason and prompt resubmission
 		-- if combat.alliance_check(User, target) and not combat.alliance_prompt() then return end
 
-		local damage, pierce_failed = combat.apply_pierce(1, magnitude(user) - target.stats.defense)
-
-		target.hp = target.hp - damage
-		if damage > 0 or pierce_failed then
-			-- Apply a small bleeding effect even if damage is 0
-			-- to help weaker characters overcome their glancing blows
-			-- Bleed scales up with damage because small defense losses will matter less to strong melee fighters.
-			local new_magnitude = 5 + damage
-			local old_magnitude = target:component("esprit:bleed") or 0
-			target:attach("esprit:bleed", old_magnitude + new_magnitude)
-		end
-
 		local damage_messages = {
 			"{self_Address}'s claws rake against {target_address}",
 			"{target_Address} is struck by {self_address}'s claws",
@@ -58,12 +43,23 @@ resources.ability "scratch" {
 			return combat.format(user, target, table[math.random(#table)])
 		end
 
-		if pierce_failed then
-			console:combat_log(pick(glance_messages), log.Glance)
-		elseif damage == 0 then
-			console:combat_log(pick(failure_messages), log.Miss)
-		else
-			console:combat_log(pick(damage_messages), log.Hit(damage))
+		local attack = { magnitude = magnitude(user), kind = "physical", pierce = 1 }
+		local damage, log = combat.damage(user, target, attack, function(log)
+			if log:glance() then
+				return pick(glance_messages)
+			elseif log:miss() then
+				return pick(failure_messages)
+			else
+				return pick(damage_messages)
+			end
+		end)
+		if not log:miss() then
+			-- Apply a small bleeding effect even if damage is 0
+			-- to help weaker characters overcome their glancing blows
+			-- Bleed scales up with damage because small defense losses will matter less to strong melee fighters.
+			local new_magnitude = 5 + damage
+			local old_magnitude = target:component("esprit:bleed") or 0
+			target:attach("esprit:bleed", old_magnitude + new_magnitude)
 		end
 
 		return use_time
@@ -1,18 +1,5 @@
 local resources = require "std:resources"
 
--- TODO: "things sheets used to contain"
-local function luvui_prototype()
-	local luvui = piece.new("esprit:luvui")
-
-	luvui:attach("esprit:level", 1)
-	luvui:attach("esprit:experience", 0)
-
-	luvui:attach("esprit:skill/major", "chaos")
-	luvui:attach("esprit:skill/minor", "positive")
-
-	return luvui
-end
-
 resources.sheet "luvui" {
 	textures = {
 		icon = resources.texture "luvui.png",
@@ -32,6 +19,10 @@ resources.sheet "luvui" {
 	},
 
 	on_consider = "std:basic",
+	components = {
+		["esprit:major"] = "chaos",
+		["esprit:minor"] = "positive",
+	},
 	nouns = {
 		name = "Luvui",
 		proper_name = true,
@@ -45,21 +36,13 @@ resources.sheet "luvui" {
 		magic = 6,
 		resistance = 3,
 	},
+	growth = {
+		{ experience = 10, stats = { heart = 4, soul = 2, magic = 1 } },
+		{ experience = 25, stats = { heart = 4, soul = 3, magic = 1, resistance = 1 } },
+		{ experience = 45, stats = { heart = 5, soul = 3, magic = 2 } },
+	},
 }
 
--- TODO: "things sheets used to contain"
-local function aris_prototype()
-	local aris = piece.new("esprit:aris")
-
-	aris:attach("esprit:level", 1)
-	aris:attach("esprit:experience", 0)
-
-	aris:attach("esprit:skill/major", "negative")
-	aris:attach("esprit:skill/minor", "chaos")
-
-	return aris
-end
-
 resources.sheet "aris" {
 	textures = {
 		icon = resources.texture "aris.png",
@@ -71,6 +54,10 @@ resources.sheet "aris" {
 	},
 
 	on_consider = "std:basic",
+	components = {
+		["esprit:major"] = "negative",
+		["esprit:minor"] = "chaos",
+	},
 	nouns = {
 		name = "Aris",
 		proper_name = true,
@@ -84,4 +71,13 @@ resources.sheet "aris" {
 		magic = 1,
 		resistance = 7,
 	},
+	growth = {
+		{ experience = 10, stats = { heart = 5, power = 1, defense = 1 } },
+		{
+			experience = 25,
+			stats = { heart = 5, soul = 2, power = 1 },
+			abilities = { "esprit:magic_missile" },
+		},
+		{ experience = 45, stats = { heart = 6, power = 2, defense = 1 } },
+	},
 }
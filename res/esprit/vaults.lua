@@ -13,3 +13,52 @@ E.......E
     E
 ]]
 }
+
+resources.vault "exit" {
+    [[
+   E
+  x.x
+ x...x
+E..>..E
+ x...x
+  x.x
+   E
+]]
+}
+
+-- Where the party arrives on each floor.
+resources.vault "entrance" {
+    [[
+  E
+ x.x
+E.@.E
+ x.x
+  E
+]]
+}
+
+-- Asymmetrical, so that rotated and mirrored placements look distinct.
+resources.vault "alcove" {
+    [[
+  E
+ x.xxx
+E.....x
+ x.x..x
+  xx..x
+   xxx
+]]
+}
+
+resources.vault_set "example" {
+    density = 6,
+    hall_ratio = 0.5,
+    -- Three floors, counting from 0.
+    final_depth = 2,
+    vaults = {
+        { "esprit:example",  weight = 3 },
+        { "esprit:alcove",   weight = 2 },
+        -- Every floor needs exactly one way in and one way out.
+        { "esprit:entrance", min = 1,   max = 1 },
+        { "esprit:exit",     min = 1,   max = 1 },
+    },
+}
@@ -31,26 +31,15 @@ function teams.init()
 	resources.component "teams" {
 		name = "Teams",
 
-		-- These functions are questionable,
-		-- but the alternative is expecting users to always modify the inner list properly
-		-- (or to provide functions for modifiying it in lib/team.lua)
-		-- They reinterpret on_(at|de)tach as insert/remove functions when provided with a string.
-		-- Providing a table opts out of this behavior (though empty tables will detach the component)
+		schema = { list = "string" },
+
+		-- Detaching with a team name as the annotation only removes that team,
+		-- and an empty list detaches the component entirely.
 
 		---@param user Piece
-		---@param previous string[]?
-		on_attach = function(user, previous)
-			local current = user:component("std:teams")
-			if current ~= nil and #current == 0 then
+		on_attach = function(user)
+			if #user:component("std:teams") == 0 then
 				user:detach("std:teams")
-				return
-			end
-			if type(current) == "string" then
-				previous = previous or {}
-				table.insert(previous, current)
-				-- This causes a recursive call!
-				-- Note that previous is not a `string`, which would cause a loop
-				user:attach("std:teams", previous)
 			end
 		end,
 		---@param user Piece
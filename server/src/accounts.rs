@@ -0,0 +1,139 @@
+//! A local store of accounts, used by routers to verify who clients claim to be.
+//!
+//! Accounts are saved as a toml table of usernames,
+//! each with a `password` field containing an argon2 hash in PHC string format:
+//!
+//! ```toml
+//! [alice]
+//! password = "$argon2id$v=19$m=19456,t=2,p=1$..."
+//! ```
+
+use crate::anyhow;
+use crate::protocol::AuthenticationError;
+use argon2::password_hash::rand_core::OsRng;
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::Argon2;
+use esprit2::anyhow::Context;
+use esprit2::prelude::*;
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::LazyLock;
+
+/// Checked against when there is no real hash to use,
+/// so that rejecting an unknown account takes as long as rejecting a wrong password.
+static DUMMY_HASH: LazyLock<Option<Box<str>>> = LazyLock::new(|| hash_password("").ok());
+
+#[derive(Clone, Debug, Default, serde::Serialize, serde::Deserialize)]
+#[serde(transparent)]
+pub struct Accounts(HashMap<Box<str>, Account>);
+
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+pub struct Account {
+	/// Never the password itself.
+	password: Box<str>,
+}
+
+impl Accounts {
+	/// Reads an account store, or creates an empty one if the file doesn't exist yet.
+	///
+	/// # Errors
+	///
+	/// Returns an error if the file could not be read or parsed.
+	pub fn open(path: &Path) -> anyhow::Result<Self> {
+		match fs::read_to_string(path) {
+			Ok(contents) => toml::from_str(&contents).context("failed to parse account store"),
+			Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Self::default()),
+			Err(e) => Err(e).context("failed to read account store"),
+		}
+	}
+
+	/// # Errors
+	///
+	/// Returns an error if the file could not be written.
+	pub fn save(&self, path: &Path) -> anyhow::Result<()> {
+		fs::write(path, toml::to_string(self)?).context("failed to write account store")
+	}
+
+	/// Creates an account, replacing any existing account with the same username.
+	///
+	/// # Errors
+	///
+	/// Returns an error if the password could not be hashed.
+	pub fn insert(&mut self, username: &str, password: &str) -> anyhow::Result<()> {
+		let password = hash_password(password)?;
+		self.0.insert(username.into(), Account { password });
+		Ok(())
+	}
+
+	/// Checks a username and password against the store.
+	///
+	/// # Errors
+	///
+	/// Unknown usernames and incorrect passwords are indistinguishable,
+	/// even in how long they take to reject,
+	/// so that clients can't probe for existing accounts.
+	///
+	/// The returned future doesn't borrow the store,
+	/// so it may be spawned rather than holding up the caller.
+	pub fn verify(
+		&self,
+		username: &str,
+		password: Option<&str>,
+	) -> impl Future<Output = Result<(), AuthenticationError>> + Send + 'static {
+		let hash = self.0.get(username).map(|account| account.password.clone());
+		let password = password.map(Box::from);
+		async move {
+			if verify_password(hash, password).await {
+				Ok(())
+			} else {
+				Err(AuthenticationError::InvalidCredentials)
+			}
+		}
+	}
+}
+
+/// Hashes a password with argon2 and a random salt, in PHC string format.
+///
+/// # Errors
+///
+/// Returns an error if the password could not be hashed.
+pub fn hash_password(password: &str) -> anyhow::Result<Box<str>> {
+	let salt = SaltString::generate(&mut OsRng);
+	Ok(Argon2::default()
+		.hash_password(password.as_bytes(), &salt)
+		.map_err(|msg| anyhow::anyhow!("failed to hash password: {msg}"))?
+		.to_string()
+		.into())
+}
+
+/// Checks a password against a hash from [`hash_password`].
+///
+/// A missing hash or password is never accepted,
+/// but is still checked against a dummy hash so that it takes just as long to reject.
+/// Hashing is deliberately slow,
+/// so this runs on a blocking thread rather than holding up the caller's task.
+pub async fn verify_password(hash: Option<Box<str>>, password: Option<Box<str>>) -> bool {
+	let present = hash.is_some() && password.is_some();
+	let verified = tokio::task::spawn_blocking(move || {
+		let Some(hash) = hash.as_deref().or(DUMMY_HASH.as_deref()) else {
+			return false;
+		};
+		let Ok(hash) = PasswordHash::new(hash) else {
+			error!("malformed password hash");
+			return false;
+		};
+		Argon2::default()
+			.verify_password(password.as_deref().unwrap_or_default().as_bytes(), &hash)
+			.is_ok()
+	})
+	.await;
+	match verified {
+		Ok(verified) => present && verified,
+		Err(msg) => {
+			error!("failed to verify password: {msg}");
+			false
+		}
+	}
+}
@@ -15,21 +15,25 @@
 use esprit2::anyhow::Context;
 use esprit2::prelude::*;
 use protocol::{
-	ArchivedClientAuthentication, ClientAuthentication, ClientIdentifier, PacketReceiver,
-	PacketSender, ServerPacket,
+	ArchivedClientHandshake, AuthenticationError, ClientAuthentication, ClientHandshake,
+	ClientIdentifier, HandshakeError, PacketReceiver, PacketSender, RoutingError, ServerPacket,
 };
 use rkyv::rancor;
 use rkyv::util::AlignedVec;
 use std::collections::HashMap;
-use std::path::Path;
+use std::fs;
+use std::path::{Path, PathBuf};
 use std::process::exit;
-use std::time::{Duration, Instant};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 use tokio::net::TcpStream;
 use tokio::select;
-use tokio::sync::mpsc;
+use tokio::sync::{mpsc, watch};
 use tokio_stream::wrappers::ReceiverStream;
 use tokio_stream::{StreamExt, StreamMap};
 
+pub mod accounts;
 pub mod protocol;
 
 pub use esprit2::anyhow;
@@ -40,9 +44,25 @@ pub struct Client {
 	sender: PacketSender,
 	_receiver: PacketReceiver,
 
+	/// When the client was last pinged.
 	pub ping: Instant,
+	/// When the client last sent a packet.
+	pub last_seen: Instant,
+	/// Round-trip time of the most recently answered ping.
+	pub latency: Option<Duration>,
+	/// Present once the client has sent a compatible handshake.
+	///
+	/// Until then, the client may only send handshakes and pings, and is not sent the world.
+	pub handshake: Option<ClientHandshake>,
 	pub authentication: Option<ClientAuthentication>,
+	/// Spectators are kept up to date with the world, but may not act.
+	pub spectator: bool,
 	pub requested_world: bool,
+	/// Sequence number of the client's most recent action,
+	/// which is acknowledged once the world is waiting for input again.
+	pub acknowledge: Option<u32>,
+	/// Whether this client has been sent the summary of a finished run.
+	pub summarized: bool,
 }
 
 impl Client {
@@ -60,8 +80,14 @@ impl Client {
 				sender: PacketSender::new(sender),
 				_receiver: receiver,
 				ping: Instant::now(),
+				last_seen: Instant::now(),
+				latency: None,
+				handshake: None,
 				authentication: None,
+				spectator: false,
 				requested_world: true,
+				acknowledge: None,
+				summarized: false,
 			},
 			stream,
 		)
@@ -76,21 +102,119 @@ impl Client {
 		Ok(())
 	}
 
-	pub async fn authenticate(
+	/// Answers the client's ping.
+	pub async fn pong(&mut self) -> anyhow::Result<()> {
+		self.sender
+			.send(&protocol::ServerPacket::Pong)
+			.await
+			.context("failed to send packet")
+	}
+
+	/// Records the latency of the client's answer to [`Client::ping`].
+	pub fn receive_pong(&mut self) {
+		self.latency = Some(self.ping.elapsed());
+	}
+
+	/// Accepts the client's handshake, or rejects it if its versions don't match the server's.
+	///
+	/// Any of `modules` (which must be within `resource_directory`) that the client is missing
+	/// are sent to it before it is accepted.
+	pub async fn handshake(
 		&mut self,
-		auth: &ArchivedClientAuthentication,
+		handshake: &ArchivedClientHandshake,
+		modules: &Vec<replay::Module>,
+		resource_directory: &Path,
 	) -> anyhow::Result<()> {
-		let auth =
-			rkyv::deserialize::<_, rancor::Error>(auth).context("failed to recieve packet")?;
+		let handshake =
+			rkyv::deserialize::<_, rancor::Error>(handshake).context("failed to recieve packet")?;
+		if let Err(error) = handshake.check() {
+			warn!("rejected client: {error}");
+			return self.reject(&error).await;
+		}
+		for module in handshake.missing_modules(modules) {
+			info!(module = module.name, "sending module");
+			self.send_module(module, &resource_directory.join(&*module.name))
+				.await?;
+		}
+		self.sender
+			.send(&ServerPacket::Accepted { modules })
+			.await
+			.context("failed to send packet")?;
+		self.handshake = Some(handshake);
+		Ok(())
+	}
+
+	async fn send_module(&mut self, module: &replay::Module, path: &Path) -> anyhow::Result<()> {
+		for relative in replay::Module::files(path).context("failed to read module")? {
+			let contents = fs::read(path.join(&relative)).context("failed to read module file")?;
+			let path = replay::Module::portable_path(&relative).into();
+			self.sender
+				.send(&ServerPacket::ModuleFile {
+					module,
+					path,
+					contents,
+				})
+				.await
+				.context("failed to send packet")?;
+		}
+		Ok(())
+	}
+
+	pub async fn reject(&mut self, error: &HandshakeError) -> anyhow::Result<()> {
+		self.sender
+			.send(&ServerPacket::Rejected(error))
+			.await
+			.context("failed to send packet")
+	}
+
+	/// Accepts the client's claimed identity.
+	///
+	/// Routers with an account store verify claims before calling this
+	/// (see [`accounts::Accounts::verify`]);
+	/// without one, clients may claim any username.
+	pub fn authenticate(&mut self, auth: ClientAuthentication) {
 		info!(username = auth.username, "authenticated");
 		self.authentication = Some(auth);
-		Ok(())
+	}
+
+	pub async fn deny(&mut self, error: &AuthenticationError) -> anyhow::Result<()> {
+		self.sender
+			.send(&ServerPacket::AuthenticationFailed(error))
+			.await
+			.context("failed to send packet")
+	}
+
+	pub async fn refuse_route(&mut self, error: &RoutingError) -> anyhow::Result<()> {
+		self.sender
+			.send(&ServerPacket::RoutingFailed(error))
+			.await
+			.context("failed to send packet")
+	}
+
+	pub async fn shut_down(&mut self) -> anyhow::Result<()> {
+		self.sender
+			.send(&ServerPacket::ShuttingDown)
+			.await
+			.context("failed to send packet")
+	}
+
+	pub async fn list_instances(
+		&mut self,
+		instances: Vec<protocol::InstanceInfo>,
+	) -> anyhow::Result<()> {
+		self.sender
+			.send(&ServerPacket::Instances(instances))
+			.await
+			.context("failed to send packet")
 	}
 }
 
 pub(crate) struct Server {
+	pub(crate) resource_directory: PathBuf,
 	pub(crate) resources: resource::Handle,
 	pub(crate) world: world::Manager,
+	pub(crate) setup: replay::Setup,
+	pub(crate) modules: Vec<replay::Module>,
 }
 
 impl Server {
@@ -98,59 +222,139 @@ impl Server {
 		resource_directory: impl AsRef<Path>,
 		lua: &mlua::Lua,
 	) -> anyhow::Result<Self> {
-		let modules = resource_directory
-			.as_ref()
-			.read_dir()
-			.context("failed to read contents of resource directory")?
-			.filter_map(|x| {
-				let x = x.ok()?;
-				if x.metadata().ok()?.is_dir() {
-					Some(x.path().into_boxed_path())
-				} else {
-					None
-				}
-			})
-			.collect::<Box<[Box<Path>]>>();
-		let (resources, errors) =
-			resource::open(lua, modules.iter().map(|x| x.as_ref()), |_, _, init| init());
-		let resources = resource::Handle::new(resources.into());
-		for (module, error) in errors
-			.into_iter()
-			.flat_map(|x| <Box<[_]> as IntoIterator>::into_iter(x.errors).map(move |e| (x.name, e)))
-		{
-			error!(module, "{error:?}");
-		}
+		let resource_directory = resource_directory.as_ref().to_path_buf();
+		let (resources, modules) = open_resources(&resource_directory, lua)?;
 
 		// Create a piece for the player, and register it with the world manager.
-		let party_blueprint = [
-			world::PartyReferenceBase {
-				sheet: "esprit:luvui".into(),
-				accent_color: (0xDA, 0x2D, 0x5C, 0xFF),
-			},
-			world::PartyReferenceBase {
-				sheet: "esprit:aris".into(),
-				accent_color: (0x0C, 0x94, 0xFF, 0xFF),
-			},
-		];
-		let mut world = world::Manager::new(party_blueprint.into_iter(), &resources)
-			.unwrap_or_else(|msg| {
-				error!("failed to initialize world manager: {msg}");
-				exit(1);
-			});
-		world.generate_floor(
-			"default seed",
-			&vault::Set {
-				vaults: vec!["esprit:example".into()],
-				density: 4,
-				hall_ratio: 1,
-			},
-			&resources,
+		let setup = replay::Setup {
+			party: vec![
+				world::PartyReferenceBase {
+					sheet: "esprit:luvui".into(),
+					accent_color: (0xDA, 0x2D, 0x5C, 0xFF),
+				},
+				world::PartyReferenceBase {
+					sheet: "esprit:aris".into(),
+					accent_color: (0x0C, 0x94, 0xFF, 0xFF),
+				},
+			],
+			vault_set: "esprit:example".into(),
+			floor_seed: "default seed".into(),
+			random_seed: rand::random(),
+		};
+		let world = setup.start(&resources, lua).unwrap_or_else(|msg| {
+			error!("failed to initialize world manager: {msg}");
+			exit(1);
+		});
+
+		Ok(Self {
+			resource_directory,
+			resources,
+			world,
+			setup,
+			modules,
+		})
+	}
+
+	/// Replaces the world with one recreated by replaying a saved run.
+	pub(crate) fn resume(
+		&mut self,
+		replay: &replay::Replay,
+		lua: &mlua::Lua,
+		console: impl console::Handle,
+	) -> anyhow::Result<()> {
+		for module in replay.mismatched_modules(&self.modules) {
+			warn!(
+				module = module.name,
+				"module differs from the one this run was started with"
+			);
+		}
+		self.world = replay::Player::new(replay, &self.resources, lua)?.finish(
+			&self.resources,
+			lua,
+			console,
 		)?;
+		self.setup = replay.setup.clone();
+		Ok(())
+	}
+
+	/// Returns the run so far.
+	pub(crate) fn replay(&self) -> replay::Replay {
+		replay::Replay::new(
+			self.setup.clone(),
+			self.modules.clone(),
+			self.world.recording.clone().unwrap_or_default(),
+		)
+	}
 
-		Ok(Self { resources, world })
+	/// Writes the run so far to a new file in `directory`.
+	pub(crate) fn save_replay(&self, directory: &Path) -> anyhow::Result<PathBuf> {
+		let replay = self.replay();
+		let bytes =
+			rkyv::to_bytes::<rancor::BoxedError>(&replay).context("failed to serialize replay")?;
+		fs::create_dir_all(directory).context("failed to create replay directory")?;
+		let timestamp = SystemTime::now()
+			.duration_since(UNIX_EPOCH)
+			.unwrap_or_default()
+			.as_secs();
+		let path = directory.join(format!("{timestamp}-{}.replay", self.setup.random_seed));
+		fs::write(&path, bytes).context("failed to write replay")?;
+		Ok(path)
 	}
 }
 
+/// Hashes every module in the resource directory, without loading them.
+///
+/// # Errors
+///
+/// Returns an error if the resource directory or any of its modules could not be read.
+pub fn modules(resource_directory: impl AsRef<Path>) -> anyhow::Result<Vec<replay::Module>> {
+	hash_modules(&module_paths(resource_directory)?)
+}
+
+fn module_paths(resource_directory: impl AsRef<Path>) -> anyhow::Result<Box<[Box<Path>]>> {
+	Ok(resource_directory
+		.as_ref()
+		.read_dir()
+		.context("failed to read contents of resource directory")?
+		.filter_map(|x| {
+			let x = x.ok()?;
+			if x.metadata().ok()?.is_dir() {
+				Some(x.path().into_boxed_path())
+			} else {
+				None
+			}
+		})
+		.collect())
+}
+
+fn hash_modules(modules: &[Box<Path>]) -> anyhow::Result<Vec<replay::Module>> {
+	let mut modules = modules
+		.iter()
+		.map(|path| replay::Module::open(path))
+		.collect::<Result<Vec<_>, _>>()
+		.context("failed to read module for replay")?;
+	modules.sort_by(|a, b| a.name.cmp(&b.name));
+	Ok(modules)
+}
+
+/// Loads every module in the resource directory, logging any errors.
+fn open_resources(
+	resource_directory: impl AsRef<Path>,
+	lua: &mlua::Lua,
+) -> anyhow::Result<(resource::Handle, Vec<replay::Module>)> {
+	let modules = module_paths(resource_directory)?;
+	let (resources, errors) =
+		resource::open(lua, modules.iter().map(|x| x.as_ref()), |_, _, init| init());
+	let resources = resource::Handle::new(resources.into());
+	for (module, error) in errors
+		.into_iter()
+		.flat_map(|x| <Box<[_]> as IntoIterator>::into_iter(x.errors).map(move |e| (x.name, e)))
+	{
+		error!(module, "{error:?}");
+	}
+	Ok((resources, hash_modules(&modules)?))
+}
+
 #[derive(Clone, Debug)]
 struct Console {
 	sender: mpsc::UnboundedSender<console::Message>,
@@ -167,6 +371,8 @@ pub struct ClientParty {
 	next_id: ClientIdentifier,
 	clients: HashMap<ClientIdentifier, Client>,
 	receiver: StreamMap<ClientIdentifier, ReceiverStream<AlignedVec>>,
+	/// Receivers of clients whose packets are being held back; see [`ClientParty::pause`].
+	paused: HashMap<ClientIdentifier, ReceiverStream<AlignedVec>>,
 }
 
 impl Default for ClientParty {
@@ -175,6 +381,7 @@ impl Default for ClientParty {
 			next_id: ClientIdentifier::default(),
 			clients: HashMap::new(),
 			receiver: StreamMap::new(),
+			paused: HashMap::new(),
 		}
 	}
 }
@@ -194,13 +401,81 @@ impl ClientParty {
 		let client = self
 			.get_mut(&id)
 			.expect("clients and receivers must have the same keys");
+		client.last_seen = Instant::now();
 		Some((id, client, packet))
 	}
 
+	/// Pings clients which are due for a heartbeat, and disconnects those which have timed out.
+	pub async fn heartbeat(&mut self) {
+		let timed_out = self
+			.clients
+			.iter()
+			.filter(|(_, client)| client.last_seen.elapsed() > protocol::TIMEOUT)
+			.map(|(id, _)| *id)
+			.collect::<Vec<_>>();
+		for id in timed_out {
+			let (client, _) = self.take(id);
+			warn!(addr = client.address, "timed out");
+		}
+		for client in self.clients.values_mut() {
+			if client.ping.elapsed() >= protocol::HEARTBEAT_INTERVAL
+				&& let Err(msg) = client.ping().await
+			{
+				error!(addr = client.address, "failed to ping client: {msg}");
+			}
+		}
+	}
+
+	/// Iterates over clients which have sent a compatible handshake.
+	pub fn accepted_mut(&mut self) -> impl Iterator<Item = &mut Client> {
+		self.clients
+			.values_mut()
+			.filter(|client| client.handshake.is_some())
+	}
+
+	/// Stops reading packets from a client until [`ClientParty::resume`] is called,
+	/// so that slow work on its behalf can finish before its next packet is handled.
+	///
+	/// A paused client's disconnection isn't noticed until it resumes.
+	pub fn pause(&mut self, id: ClientIdentifier) {
+		if let Some(receiver) = self.receiver.remove(&id) {
+			self.paused.insert(id, receiver);
+		}
+	}
+
+	/// Continues reading packets from a paused client.
+	///
+	/// Returns false if the client was not paused,
+	/// such as when it timed out while waiting.
+	pub fn resume(&mut self, id: ClientIdentifier) -> bool {
+		if let Some(receiver) = self.paused.remove(&id) {
+			self.receiver.insert(id, receiver);
+			true
+		} else {
+			false
+		}
+	}
+
+	/// Removes clients whose connections have closed.
+	pub fn prune(&mut self) {
+		let receiver = &self.receiver;
+		let paused = &self.paused;
+		self.clients.retain(|id, client| {
+			let connected = receiver.contains_key(id) || paused.contains_key(id);
+			if !connected {
+				info!(addr = client.address, "disconnected");
+			}
+			connected
+		});
+	}
+
 	pub fn take(&mut self, id: ClientIdentifier) -> (Client, ReceiverStream<AlignedVec>) {
 		(
 			self.clients.remove(&id).expect("id must be valid"),
-			self.receiver.remove(&id).expect("id must be valid"),
+			self.receiver
+				.remove(&id)
+				.or_else(|| self.paused.remove(&id))
+				.expect("id must be valid"),
 		)
 	}
 }
@@ -218,35 +493,72 @@ impl std::ops::DerefMut for ClientParty {
 	}
 }
 
+fn register_runtime(
+	lua: &mlua::Lua,
+	resources: resource::Handle,
+	console: impl console::Handle + Clone + 'static,
+) -> anyhow::Result<()> {
+	lua.load_from_function::<mlua::Value>(
+		"runtime.resources",
+		lua.create_function(move |_, ()| Ok(resources.clone()))?,
+	)?;
+	lua.load_from_function::<mlua::Value>(
+		"runtime.console",
+		lua.create_function(move |_, ()| Ok(console::LuaHandle(console.clone())))?,
+	)?;
+	Ok(())
+}
+
+/// Runs an instance until all of its clients have left, or until `shutdown` is set.
+///
+/// If `replays` is provided, the run is saved there once it ends (or once the instance closes).
+/// `players` is kept up to date with the number of connected clients.
+///
+/// If `resume` is provided, the instance continues that run instead of starting a new one,
+/// and stays open until at least one client has joined.
+/// When shut down in the middle of a run, the run so far is returned so that it may be resumed later.
+///
 /// # Errors
 ///
 /// Returns an error if the instance cannot be initialized.
 pub fn instance(
 	mut router: mpsc::Receiver<(Client, ReceiverStream<AlignedVec>)>,
 	res: impl AsRef<Path>,
-) -> anyhow::Result<()> {
+	replays: Option<PathBuf>,
+	players: Arc<AtomicUsize>,
+	resume: Option<replay::Replay>,
+	mut shutdown: watch::Receiver<bool>,
+) -> anyhow::Result<Option<replay::Replay>> {
 	let lua = esprit2::lua::init()?;
 
 	let (sender, mut console_reciever) = mpsc::unbounded_channel();
 	let console = Console { sender };
+	// Kept apart from the world's console so that chat is never bundled into acknowledgements,
+	// which clients only print after a misprediction.
+	let (sender, mut chat_reciever) = mpsc::unbounded_channel();
+	let chat = Console { sender };
 	let mut server = Server::new(res, &lua)?;
 	let mut clients = ClientParty::default();
+	let mut replay_saved = false;
+	// Instances close once empty, but not before anyone has had a chance to join.
+	let mut occupied = false;
+	let mut interrupted = false;
 
-	let resources = server.resources.clone();
-	lua.load_from_function::<mlua::Value>(
-		"runtime.resources",
-		lua.create_function(move |_, ()| Ok(resources.clone()))?,
-	)?;
-	let console_handle = console.clone();
-	lua.load_from_function::<mlua::Value>(
-		"runtime.console",
-		lua.create_function(move |_, ()| Ok(console::LuaHandle(console_handle.clone())))?,
-	)?;
+	register_runtime(&lua, server.resources.clone(), console.clone())?;
 
-	tokio::runtime::Builder::new_multi_thread()
+	if let Some(replay) = &resume {
+		server
+			.resume(replay, &lua, &console)
+			.context("failed to resume run")?;
+		// Players have already seen these messages.
+		while console_reciever.try_recv().is_ok() {}
+	}
+
+	let unfinished = tokio::runtime::Builder::new_multi_thread()
 		.enable_all()
 		.build()?
 		.block_on(async move {
+			let mut heartbeat = tokio::time::interval(Duration::from_secs(1));
 			// This function is unusually lenient of errors in order to avoid unexpected shutdowns.
 			'server: loop {
 				select! {
@@ -254,7 +566,7 @@ pub fn instance(
 						clients.join(client, receiver);
 					}
 					Some(i) = console_reciever.recv() => {
-						for client in clients.values_mut() {
+						for client in clients.accepted_mut() {
 							if let Err(msg) = client
 								.sender
 								.send(&protocol::ServerPacket::Message(&i))
@@ -264,11 +576,23 @@ pub fn instance(
 							}
 						}
 					}
+					Some(i) = chat_reciever.recv() => {
+						for client in clients.accepted_mut() {
+							if let Err(msg) = client
+								.sender
+								.send(&protocol::ServerPacket::Message(&i))
+								.await
+							{
+								error!("failed to send chat message to client: {msg}");
+							}
+						}
+					}
 					Some((_id, client, packet)) = clients.next() => {
 						if let Err(msg) = client_tick(
 							client,
 							packet,
 							&console,
+							&chat,
 							&lua,
 							&mut server,
 						)
@@ -276,13 +600,17 @@ pub fn instance(
 							error!("client action failed: {msg}");
 						}
 					}
+					_ = heartbeat.tick() => clients.heartbeat().await,
+					_ = shutdown.wait_for(|x| *x) => {
+						interrupted = true;
+						break 'server;
+					}
 					_ = tokio::time::sleep(Duration::from_millis(1)) => {
 					}
 				}
 
 				loop {
 					match server.world.tick(&server.resources, &lua, &console) {
-						// TODO: infinite loop when the player dies please fix. (how)
 						Ok(true) => (),
 						Ok(false) => break,
 						Err(msg) => {
@@ -292,8 +620,43 @@ pub fn instance(
 					}
 				}
 
+				if clients.values().any(|client| client.acknowledge.is_some()) {
+					let mut messages = Vec::new();
+					while let Ok(message) = console_reciever.try_recv() {
+						messages.push(message);
+					}
+					let checksum = protocol::world_checksum(&server.world);
+					for client in clients.accepted_mut() {
+						let result = if let Some(sequence) = client.acknowledge.take() {
+							client
+								.sender
+								.send(&ServerPacket::Acknowledge {
+									sequence,
+									checksum,
+									messages: messages.clone(),
+								})
+								.await
+						} else {
+							// Only the acting client predicted this action,
+							// so everyone else needs the whole world to see what happened.
+							client.requested_world = true;
+							let mut result = Ok(());
+							for message in &messages {
+								result = client.sender.send(&ServerPacket::Message(message)).await;
+								if result.is_err() {
+									break;
+								}
+							}
+							result
+						};
+						if let Err(msg) = result {
+							error!("failed to acknowledge action: {msg}");
+						}
+					}
+				}
+
 				let mut world_packet = None;
-				for client in clients.values_mut() {
+				for client in clients.accepted_mut() {
 					if client.requested_world {
 						client.requested_world = false;
 						let packet = if let Some(packet) = &mut world_packet {
@@ -314,13 +677,90 @@ pub fn instance(
 					}
 				}
 
-				if clients.clients.is_empty() {
-					// TODO: Save to disk
+				clients.prune();
+				players.store(
+					clients.values().filter(|client| !client.spectator).count(),
+					Ordering::Relaxed,
+				);
+				occupied |= !clients.is_empty();
+
+				if !replay_saved
+					&& let Some(replays) = &replays
+					&& (server.world.outcome.is_some() || (occupied && clients.is_empty()))
+				{
+					replay_saved = true;
+					match server.save_replay(replays) {
+						Ok(path) => info!(path = %path.display(), "saved replay"),
+						Err(msg) => error!("failed to save replay: {msg:?}"),
+					}
+				}
+
+				if let Some(summary) = server.world.summary() {
+					for client in clients.accepted_mut().filter(|client| !client.summarized) {
+						client.summarized = true;
+						if let Err(msg) =
+							client.sender.send(&ServerPacket::RunEnded(&summary)).await
+						{
+							error!("failed to send run summary to client: {msg}");
+						}
+					}
+				}
+
+				if occupied && clients.is_empty() {
 					info!("no clients remain; closing instance");
 					break;
 				}
 			}
+
+			if interrupted {
+				info!("shutting down instance");
+				for client in clients.values_mut() {
+					if let Err(msg) = client.shut_down().await {
+						error!("failed to notify client of shutdown: {msg}");
+					}
+				}
+			}
+			(interrupted && server.world.outcome.is_none()).then(|| server.replay())
 		});
+	Ok(unfinished)
+}
+
+#[derive(Clone, Debug)]
+struct StdoutConsole;
+
+impl console::Handle for StdoutConsole {
+	fn send_message(&self, message: console::Message) {
+		println!("{}", message.text);
+	}
+}
+
+/// Plays a replay without any clients, printing its console output.
+///
+/// # Errors
+///
+/// Returns an error if the replay could not be read, or if it desyncs.
+pub fn play_replay(path: impl AsRef<Path>, res: impl AsRef<Path>) -> anyhow::Result<()> {
+	let bytes = fs::read(path).context("failed to read replay")?;
+	let replay = rkyv::from_bytes::<replay::Replay, rancor::Error>(&bytes)
+		.context("failed to parse replay")?;
+
+	let lua = esprit2::lua::init()?;
+	let (resources, modules) = open_resources(res, &lua)?;
+	for module in replay.mismatched_modules(&modules) {
+		warn!(
+			module = module.name,
+			"module differs from the one used to record this replay"
+		);
+	}
+	register_runtime(&lua, resources.clone(), StdoutConsole)?;
+
+	let world =
+		replay::Player::new(&replay, &resources, &lua)?.finish(&resources, &lua, StdoutConsole)?;
+	info!(
+		actions = replay.entries.len(),
+		outcome = ?world.outcome,
+		"replay finished"
+	);
 	Ok(())
 }
 
@@ -328,6 +768,7 @@ async fn client_tick(
 	client: &mut Client,
 	packet: AlignedVec,
 	console_handle: &Console,
+	chat: &Console,
 	lua: &mlua::Lua,
 	server: &mut Server,
 ) -> anyhow::Result<()> {
@@ -342,9 +783,30 @@ async fn client_tick(
 	let _span = span.entered();
 
 	let packet = rkyv::access::<_, rancor::Error>(&packet).context("failed to read packet")?;
+	if client.handshake.is_none()
+		&& !matches!(
+			packet,
+			protocol::ArchivedClientPacket::Handshake(_)
+				| protocol::ArchivedClientPacket::Ping
+				| protocol::ArchivedClientPacket::Pong
+		) {
+		return client.reject(&HandshakeError::Missing).await;
+	}
 	match packet {
-		protocol::ArchivedClientPacket::Ping => client.ping().await?,
-		protocol::ArchivedClientPacket::Action { action } => {
+		protocol::ArchivedClientPacket::Handshake(handshake) => {
+			client
+				.handshake(handshake, &server.modules, &server.resource_directory)
+				.await?;
+		}
+		protocol::ArchivedClientPacket::Ping => client.pong().await?,
+		protocol::ArchivedClientPacket::Pong => client.receive_pong(),
+		protocol::ArchivedClientPacket::Action { sequence, action } => {
+			// Acknowledge even rejected actions so that the client notices its misprediction.
+			client.acknowledge = Some(sequence.to_native());
+			if client.spectator {
+				warn!("spectator attempted to act");
+				return Ok(());
+			}
 			let action: character::Action = rkyv::deserialize::<_, rancor::Error>(action)
 				.context("failed to deserialize action packet")?;
 			let console = console_handle;
@@ -363,15 +825,42 @@ async fn client_tick(
 				warn!("client attempted to move piece it did not own");
 			}
 		}
-		protocol::ArchivedClientPacket::Authenticate(auth) => {
-			let client_authentication = rkyv::deserialize::<_, rancor::Error>(auth)
-				.context("failed to deserialize client authentication packet")?;
-			info!(username = client_authentication.username, "authenticated");
-			client.authentication = Some(client_authentication);
+		protocol::ArchivedClientPacket::RequestWorld => client.requested_world = true,
+		protocol::ArchivedClientPacket::Chat(text) => {
+			let text = text.trim();
+			if !text.is_empty() {
+				let username = client
+					.authentication
+					.as_ref()
+					.map_or(&*client.address, |auth| auth.username.as_str());
+				let text = text
+					.chars()
+					.take(protocol::MAX_CHAT_LENGTH)
+					.collect::<String>();
+				info!(text, "chat");
+				chat.chat(username, text);
+			}
+		}
+		// Routers are responsible for verifying accounts,
+		// so an identity the client already has must not be replaced.
+		// Claims are only taken at their word when nothing verified them beforehand,
+		// such as when there is no router at all.
+		protocol::ArchivedClientPacket::Authenticate(_) if client.authentication.is_some() => {
+			warn!("already authenticated client attempted to authenticate again");
 		}
+		protocol::ArchivedClientPacket::Authenticate(auth) => client.authenticate(
+			rkyv::deserialize::<_, rancor::Error>(auth).context("failed to recieve packet")?,
+		),
 		// Client is already routed, but a singular server instance without a router may be sent superfluous routing packets.
 		// Ignore them and act as usual and clients should connect just fine.
-		protocol::ArchivedClientPacket::Instantiate | protocol::ArchivedClientPacket::Route(_) => {}
+		// The one exception is a player asking to spectate instead; spectators may never become players.
+		protocol::ArchivedClientPacket::Route(routing) => {
+			if routing.spectate {
+				client.spectator = true;
+			}
+		}
+		protocol::ArchivedClientPacket::Instantiate
+		| protocol::ArchivedClientPacket::ListInstances => {}
 	}
 	Ok(())
 }
@@ -1,5 +1,4 @@
 #![feature(maybe_uninit_fill, core_io_borrowed_buf, read_buf)]
-#![expect(clippy::unwrap_used)]
 
 use clap::Parser;
 use esprit2::anyhow::Context;
@@ -7,12 +6,15 @@ use esprit2::prelude::*;
 use esprit2_server::*;
 use rkyv::util::AlignedVec;
 use std::net::Ipv4Addr;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::process::exit;
-use std::thread;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use std::{fs, io, thread};
 use tokio::net::TcpListener;
 use tokio::select;
-use tokio::sync::mpsc;
+use tokio::sync::{mpsc, watch};
 use tokio_stream::wrappers::ReceiverStream;
 
 #[derive(clap::Parser)]
@@ -21,13 +23,440 @@ struct Cli {
 	port: Option<u16>,
 	#[clap(long, default_value = "256")]
 	instances: u32,
+	/// Directory to save replays of finished runs to.
+	#[clap(long)]
+	replays: Option<PathBuf>,
+	/// Play a replay without starting the server, then exit.
+	#[clap(long)]
+	play: Option<PathBuf>,
+	/// Require clients to log into an account from this file.
+	#[clap(long)]
+	accounts: Option<PathBuf>,
+	/// Create (or change the password of) an account, reading its password from stdin, then exit.
+	///
+	/// Requires `--accounts`.
+	#[clap(long, requires = "accounts")]
+	add_account: Option<Box<str>>,
+	/// Directory to save unfinished runs to when shutting down, which are resumed on startup.
+	#[clap(long)]
+	saves: Option<PathBuf>,
 
 	resource_directory: PathBuf,
 }
 
+/// An instance which was still running when the router shut down.
+#[derive(rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
+struct SavedInstance {
+	name: Box<str>,
+	password_hash: Option<Box<str>>,
+	replay: replay::Replay,
+}
+
 struct Instance {
-	handle: thread::JoinHandle<anyhow::Result<()>>,
+	handle: thread::JoinHandle<anyhow::Result<Option<replay::Replay>>>,
 	router: mpsc::Sender<(Client, ReceiverStream<AlignedVec>)>,
+	name: Box<str>,
+	/// An argon2 hash of the password required of clients routing to this instance, if present.
+	///
+	/// See [`accounts::hash_password`].
+	password_hash: Option<Box<str>>,
+	players: Arc<AtomicUsize>,
+}
+
+impl Instance {
+	fn spawn(
+		i: usize,
+		cli: &Cli,
+		name: Box<str>,
+		password_hash: Option<Box<str>>,
+		resume: Option<replay::Replay>,
+		shutdown: watch::Receiver<bool>,
+	) -> Self {
+		let (router, reciever) = mpsc::channel(4);
+		let players = Arc::new(AtomicUsize::new(0));
+		Self {
+			handle: thread::Builder::new()
+				.name(format!("instance {i}"))
+				.spawn({
+					let res = cli.resource_directory.clone();
+					let replays = cli.replays.clone();
+					let players = players.clone();
+					move || {
+						esprit2_server::instance(reciever, res, replays, players, resume, shutdown)
+					}
+				})
+				.expect("failed to spawn instance thread"),
+			router,
+			name,
+			password_hash,
+			players,
+		}
+	}
+
+	fn is_running(&self) -> bool {
+		!self.handle.is_finished()
+	}
+
+	/// Waits for the instance's thread to exit, returning its unfinished run.
+	fn join(self, i: usize) -> Option<SavedInstance> {
+		match self.handle.join() {
+			Ok(Ok(replay)) => replay.map(|replay| SavedInstance {
+				name: self.name,
+				password_hash: self.password_hash,
+				replay,
+			}),
+			Ok(Err(msg)) => {
+				error!(instance = i, "instance failed: {msg:?}");
+				None
+			}
+			Err(_) => {
+				error!(instance = i, "instance panicked");
+				None
+			}
+		}
+	}
+}
+
+/// The result of slow password work done on a client's behalf.
+///
+/// Argon2 is deliberately slow, so it's spawned rather than awaited by the router,
+/// and the client is paused until its result comes back.
+/// See [`ClientParty::pause`].
+enum Verified {
+	/// The client's credentials were checked against the account store.
+	Account {
+		id: protocol::ClientIdentifier,
+		auth: protocol::ClientAuthentication,
+		result: Result<(), protocol::AuthenticationError>,
+	},
+	/// The client's instance password was checked against the instance in slot `instance`.
+	Route {
+		id: protocol::ClientIdentifier,
+		instance: usize,
+		/// Identifies the instance that was checked, in case its slot was reused in the meantime.
+		password_hash: Box<str>,
+		spectate: bool,
+		correct: bool,
+	},
+	/// The password of a new instance was hashed.
+	Instantiate {
+		id: protocol::ClientIdentifier,
+		name: Box<str>,
+		password_hash: anyhow::Result<Box<str>>,
+	},
+}
+
+impl Verified {
+	fn id(&self) -> protocol::ClientIdentifier {
+		match self {
+			Verified::Account { id, .. }
+			| Verified::Route { id, .. }
+			| Verified::Instantiate { id, .. } => *id,
+		}
+	}
+}
+
+fn free_slot(instances: &mut [Option<Instance>]) -> Option<(usize, &mut Option<Instance>)> {
+	instances
+		.iter_mut()
+		.enumerate()
+		.find(|(_, x)| x.as_ref().is_none_or(|x| !x.is_running()))
+}
+
+/// Joins the threads of instances which have closed, freeing their slots.
+fn reap(instances: &mut [Option<Instance>]) {
+	for (i, slot) in instances.iter_mut().enumerate() {
+		if slot.as_ref().is_some_and(|x| !x.is_running())
+			&& let Some(instance) = slot.take()
+		{
+			instance.join(i);
+		}
+	}
+}
+
+/// Starts an instance for each unfinished run in `saves`.
+///
+/// Saves are removed once loaded, since they will be saved again at the next shutdown.
+fn restore(
+	instances: &mut [Option<Instance>],
+	cli: &Cli,
+	saves: &Path,
+	shutdown: &watch::Sender<bool>,
+) -> anyhow::Result<()> {
+	let entries = match saves.read_dir() {
+		Ok(entries) => entries,
+		Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+		Err(e) => return Err(e).context("failed to read save directory"),
+	};
+	for entry in entries {
+		let path = entry.context("failed to read save directory")?.path();
+		if path.extension().is_none_or(|x| x != "instance") {
+			continue;
+		}
+		let saved = fs::read(&path)
+			.context("failed to read save")
+			.and_then(|bytes| {
+				rkyv::from_bytes::<SavedInstance, rkyv::rancor::Error>(&bytes)
+					.context("failed to parse save")
+			});
+		let saved = match saved {
+			Ok(saved) => saved,
+			Err(msg) => {
+				error!(path = %path.display(), "{msg:?}");
+				continue;
+			}
+		};
+		let Some((i, slot)) = free_slot(instances) else {
+			warn!(path = %path.display(), "no free instance slots to restore save into");
+			break;
+		};
+		info!(instance = i, name = saved.name, "restoring instance");
+		*slot = Some(Instance::spawn(
+			i,
+			cli,
+			saved.name,
+			saved.password_hash,
+			Some(saved.replay),
+			shutdown.subscribe(),
+		));
+		fs::remove_file(&path).context("failed to remove save")?;
+	}
+	Ok(())
+}
+
+/// Writes an unfinished run to `saves`, to be restored on the next startup.
+fn save(saves: &Path, i: usize, saved: &SavedInstance) -> anyhow::Result<()> {
+	let bytes = rkyv::to_bytes::<rkyv::rancor::Error>(saved).context("failed to serialize save")?;
+	fs::create_dir_all(saves).context("failed to create save directory")?;
+	fs::write(saves.join(format!("{i}.instance")), bytes).context("failed to write save")
+}
+
+/// Resolves once the router is asked to stop, by SIGINT or (on unix) SIGTERM.
+async fn shutdown_signal() -> io::Result<()> {
+	#[cfg(unix)]
+	{
+		use tokio::signal::unix::{signal, SignalKind};
+		let mut terminate = signal(SignalKind::terminate())?;
+		select! {
+			result = tokio::signal::ctrl_c() => result,
+			_ = terminate.recv() => Ok(()),
+		}
+	}
+	#[cfg(not(unix))]
+	tokio::signal::ctrl_c().await
+}
+
+/// Starts a new instance in the first free slot of `instances`, moving `client` into it.
+///
+/// Only the password's hash is kept, both in memory and in saves;
+/// see [`accounts::hash_password`].
+async fn instantiate(
+	instances: &mut [Option<Instance>],
+	cli: &Cli,
+	clients: &mut ClientParty,
+	id: protocol::ClientIdentifier,
+	name: Option<Box<str>>,
+	password_hash: Option<Box<str>>,
+	shutdown: &watch::Sender<bool>,
+) -> anyhow::Result<()> {
+	let Some((i, slot)) = free_slot(instances) else {
+		warn!("no free instance slots");
+		return clients
+			.get_mut(&id)
+			.expect("id must be valid")
+			.refuse_route(&protocol::RoutingError::Full)
+			.await;
+	};
+	let name = name.unwrap_or_else(|| format!("instance {i}").into());
+	info!(instance = i, name, "created instance");
+	let instance = slot.insert(Instance::spawn(
+		i,
+		cli,
+		name,
+		password_hash,
+		None,
+		shutdown.subscribe(),
+	));
+	instance
+		.router
+		.send(clients.take(id))
+		.await
+		.context("failed to route client")
+}
+
+/// Moves a client into a running instance.
+async fn join(
+	instance: &Instance,
+	clients: &mut ClientParty,
+	id: protocol::ClientIdentifier,
+	spectate: bool,
+) -> anyhow::Result<()> {
+	let (mut client, receiver) = clients.take(id);
+	client.spectator = spectate;
+	instance
+		.router
+		.send((client, receiver))
+		.await
+		.context("failed to route client")
+}
+
+/// Moves a client into an existing instance, or creates a named instance if it doesn't exist yet
+/// (unless the client only wants to spectate).
+///
+/// Instance passwords are verified or hashed off of the router's loop,
+/// finishing in [`finish_verification`].
+async fn route(
+	instances: &mut [Option<Instance>],
+	cli: &Cli,
+	clients: &mut ClientParty,
+	id: protocol::ClientIdentifier,
+	routing: protocol::ClientRouting,
+	shutdown: &watch::Sender<bool>,
+	verified: &mpsc::UnboundedSender<Verified>,
+) -> anyhow::Result<()> {
+	let client = clients.get_mut(&id).expect("id must be valid");
+	let running = instances
+		.iter()
+		.enumerate()
+		.filter_map(|(i, x)| x.as_ref().filter(|x| x.is_running()).map(|x| (i, x)));
+	let instance = match &routing.instance {
+		protocol::InstanceTarget::Id(instance_id) => {
+			running.clone().find(|(i, _)| *i == *instance_id as usize)
+		}
+		protocol::InstanceTarget::Name(name) => running.clone().find(|(_, x)| x.name == *name),
+	};
+	match (instance, routing.instance) {
+		(Some((i, instance)), _) => {
+			let Some(password_hash) = instance.password_hash.clone() else {
+				return join(instance, clients, id, routing.spectate).await;
+			};
+			clients.pause(id);
+			let verify =
+				accounts::verify_password(Some(password_hash.clone()), routing.instance_password);
+			let verified = verified.clone();
+			tokio::spawn(async move {
+				let correct = verify.await;
+				let _ = verified.send(Verified::Route {
+					id,
+					instance: i,
+					password_hash,
+					spectate: routing.spectate,
+					correct,
+				});
+			});
+			Ok(())
+		}
+		(None, protocol::InstanceTarget::Name(name)) if !routing.spectate => {
+			let Some(password) = routing.instance_password else {
+				return instantiate(instances, cli, clients, id, Some(name), None, shutdown).await;
+			};
+			clients.pause(id);
+			let verified = verified.clone();
+			tokio::spawn(async move {
+				let password_hash =
+					tokio::task::spawn_blocking(move || accounts::hash_password(&password))
+						.await
+						.context("failed to hash instance password")
+						.flatten();
+				let _ = verified.send(Verified::Instantiate {
+					id,
+					name,
+					password_hash,
+				});
+			});
+			Ok(())
+		}
+		(None, _) => {
+			client
+				.refuse_route(&protocol::RoutingError::NoSuchInstance)
+				.await
+		}
+	}
+}
+
+/// Finishes whatever a client was paused for, once its password work is done.
+///
+/// The client must have been resumed, and still be in `clients`.
+async fn finish_verification(
+	instances: &mut [Option<Instance>],
+	cli: &Cli,
+	clients: &mut ClientParty,
+	verified: Verified,
+	shutdown: &watch::Sender<bool>,
+) -> anyhow::Result<()> {
+	match verified {
+		Verified::Account { id, auth, result } => {
+			let client = clients.get_mut(&id).expect("id must be valid");
+			match result {
+				Ok(()) => {
+					client.authenticate(auth);
+					Ok(())
+				}
+				Err(error) => {
+					warn!(username = auth.username, "failed to authenticate: {error}");
+					client.deny(&error).await
+				}
+			}
+		}
+		Verified::Route {
+			id,
+			instance: i,
+			password_hash,
+			spectate,
+			correct,
+		} => {
+			let instance = instances[i]
+				.as_ref()
+				.filter(|x| x.is_running() && x.password_hash.as_ref() == Some(&password_hash));
+			let client = clients.get_mut(&id).expect("id must be valid");
+			match instance {
+				Some(instance) if correct => join(instance, clients, id, spectate).await,
+				Some(_) => {
+					warn!(instance = i, "incorrect instance password");
+					client
+						.deny(&protocol::AuthenticationError::InstancePassword)
+						.await
+				}
+				None => {
+					client
+						.refuse_route(&protocol::RoutingError::NoSuchInstance)
+						.await
+				}
+			}
+		}
+		Verified::Instantiate {
+			id,
+			name,
+			password_hash,
+		} => {
+			instantiate(
+				instances,
+				cli,
+				clients,
+				id,
+				Some(name),
+				Some(password_hash?),
+				shutdown,
+			)
+			.await
+		}
+	}
+}
+
+fn directory(instances: &[Option<Instance>]) -> Vec<protocol::InstanceInfo> {
+	instances
+		.iter()
+		.enumerate()
+		.filter_map(|(i, x)| {
+			let x = x.as_ref().filter(|x| x.is_running())?;
+			Some(protocol::InstanceInfo {
+				id: i as u32,
+				name: x.name.clone(),
+				players: x.players.load(Ordering::Relaxed) as u32,
+				password: x.password_hash.is_some(),
+			})
+		})
+		.collect()
 }
 
 #[tokio::main]
@@ -40,6 +469,32 @@ async fn main() -> anyhow::Result<()> {
 		.without_time()
 		.init();
 
+	if let Some(replay) = &cli.play {
+		return play_replay(replay, &cli.resource_directory);
+	}
+
+	if let Some(username) = &cli.add_account
+		&& let Some(path) = &cli.accounts
+	{
+		let mut accounts = accounts::Accounts::open(path)?;
+		let mut password = String::new();
+		std::io::stdin()
+			.read_line(&mut password)
+			.context("failed to read password")?;
+		accounts.insert(username, password.trim_end_matches(['\r', '\n']))?;
+		accounts.save(path)?;
+		info!(username, "saved account");
+		return Ok(());
+	}
+
+	let accounts = cli
+		.accounts
+		.as_deref()
+		.map(accounts::Accounts::open)
+		.transpose()?;
+	// Clients are expected to load the same modules that instances will.
+	let modules = esprit2_server::modules(&cli.resource_directory)?;
+
 	let listener = TcpListener::bind((
 		Ipv4Addr::new(127, 0, 0, 1),
 		cli.port.unwrap_or(protocol::DEFAULT_PORT),
@@ -62,10 +517,40 @@ async fn main() -> anyhow::Result<()> {
 	let mut instances = Box::new_uninit_slice(cli.instances as usize);
 	let instances: &mut [Option<Instance>] = instances.write_with(|_| None);
 	let mut clients = ClientParty::default();
+	let (shutdown, _) = watch::channel(false);
+
+	if let Some(saves) = &cli.saves {
+		restore(instances, &cli, saves, &shutdown)?;
+	}
+
+	let shutdown_signal = shutdown_signal();
+	tokio::pin!(shutdown_signal);
+	let mut heartbeat = tokio::time::interval(Duration::from_secs(1));
+	let (verified_sender, mut verified_receiver) = mpsc::unbounded_channel::<Verified>();
 
 	info!("listening");
 	loop {
 		select! {
+			result = &mut shutdown_signal => {
+				if let Err(msg) = result {
+					error!("failed to listen for shutdown signal: {msg}");
+				}
+				break;
+			}
+			_ = heartbeat.tick() => clients.heartbeat().await,
+			Some(verified) = verified_receiver.recv() => {
+				// The client may have timed out while waiting.
+				if clients.resume(verified.id()) {
+					let _span = tracing::error_span!(
+						"client",
+						addr = clients[&verified.id()].address,
+					)
+					.entered();
+					if let Err(msg) = finish_verification(instances, &cli, &mut clients, verified, &shutdown).await {
+						error!("failed to finish verification: {msg:?}");
+					}
+				}
+			}
 			stream = listener.accept() => {
 				match stream {
 					Ok((stream, address)) => {
@@ -88,36 +573,126 @@ async fn main() -> anyhow::Result<()> {
 				}
 				let _span = span.entered();
 
-				let packet = rkyv::access::<_, rkyv::rancor::Error>(&packet).context("failed to read packet")?;
-				match packet {
-					protocol::ArchivedClientPacket::Ping => client.ping().await.unwrap(),
-					protocol::ArchivedClientPacket::Authenticate(auth) => client.authenticate(auth).await.unwrap(),
-					protocol::ArchivedClientPacket::Instantiate => {
-						if let Some((i, instance)) = instances.iter_mut().enumerate().find(|(_, x)| x.as_ref().is_none_or(|x| x.handle.is_finished())) {
-							let (router, reciever) = mpsc::channel(4);
-							router.send(clients.take(id)).await.unwrap();
-							*instance = Some(Instance {
-								handle: thread::Builder::new()
-									.name(format!("instance {i}"))
-									.spawn({
-										let res = cli.resource_directory.clone();
-										move || esprit2_server::instance(reciever, res)
-									})
-									.expect("failed to spawn instance thread"),
-								router,
-							});
+				// Breaking out of this (rather than continuing the loop) still prunes clients and reaps instances.
+				'packet: {
+					let packet = match rkyv::access::<_, rkyv::rancor::Error>(&packet) {
+						Ok(packet) => packet,
+						Err(msg) => {
+							error!("failed to read packet: {msg}");
+							break 'packet;
 						}
+					};
+					if client.handshake.is_none()
+						&& !matches!(packet, protocol::ArchivedClientPacket::Handshake(_) | protocol::ArchivedClientPacket::Ping | protocol::ArchivedClientPacket::Pong)
+					{
+						if let Err(msg) = client.reject(&protocol::HandshakeError::Missing).await {
+							error!("failed to reject client: {msg}");
+						}
+						break 'packet;
+					}
+					if accounts.is_some()
+						&& client.authentication.is_none()
+						&& matches!(packet, protocol::ArchivedClientPacket::Instantiate | protocol::ArchivedClientPacket::Route(_))
+					{
+						if let Err(msg) = client.deny(&protocol::AuthenticationError::Unauthenticated).await {
+							error!("failed to deny client: {msg}");
+						}
+						break 'packet;
 					}
-					protocol::ArchivedClientPacket::Route(routing) => {
-						if let Some(Some(instance)) = instances.get(routing.instance_id.to_native() as usize) {
-							instance.router.send(clients.take(id)).await.unwrap();
-						} else {
-							todo!()
+					match packet {
+						protocol::ArchivedClientPacket::Handshake(handshake) => {
+							if let Err(msg) = client.handshake(handshake, &modules, &cli.resource_directory).await {
+								error!("failed to handle handshake: {msg}");
+							}
+						}
+						protocol::ArchivedClientPacket::Ping => {
+							if let Err(msg) = client.pong().await {
+								error!("failed to answer ping: {msg}");
+							}
+						}
+						protocol::ArchivedClientPacket::Pong => client.receive_pong(),
+						protocol::ArchivedClientPacket::Authenticate(auth) => {
+							let auth = match rkyv::deserialize::<_, rkyv::rancor::Error>(auth) {
+								Ok(auth) => auth,
+								Err(msg) => {
+									error!("failed to deserialize authentication packet: {msg}");
+									break 'packet;
+								}
+							};
+							let Some(accounts) = &accounts else {
+								client.authenticate(auth);
+								break 'packet;
+							};
+							clients.pause(id);
+							let verify = accounts.verify(&auth.username, auth.password.as_deref());
+							let verified = verified_sender.clone();
+							tokio::spawn(async move {
+								let result = verify.await;
+								let _ = verified.send(Verified::Account { id, auth, result });
+							});
 						}
-					},
-					protocol::ArchivedClientPacket::Action { .. } => todo!(),
+						protocol::ArchivedClientPacket::Instantiate => {
+							if let Err(msg) = instantiate(instances, &cli, &mut clients, id, None, None, &shutdown).await {
+								error!("failed to create instance: {msg}");
+							}
+						}
+						protocol::ArchivedClientPacket::Route(routing) => {
+							let routing = match rkyv::deserialize::<_, rkyv::rancor::Error>(routing) {
+								Ok(routing) => routing,
+								Err(msg) => {
+									error!("failed to deserialize routing packet: {msg}");
+									break 'packet;
+								}
+							};
+							if let Err(msg) = route(instances, &cli, &mut clients, id, routing, &shutdown, &verified_sender).await {
+								error!("failed to route client: {msg}");
+							}
+						}
+						protocol::ArchivedClientPacket::ListInstances => {
+							if let Err(msg) = client.list_instances(directory(instances)).await {
+								error!("failed to list instances: {msg}");
+							}
+						}
+						protocol::ArchivedClientPacket::Action { .. }
+						| protocol::ArchivedClientPacket::RequestWorld
+						| protocol::ArchivedClientPacket::Chat(_) => {
+							if let Err(msg) = client.refuse_route(&protocol::RoutingError::NotRouted).await {
+								error!("failed to refuse route: {msg}");
+							}
+						}
+					}
 				}
 			}
 		}
+		clients.prune();
+		reap(instances);
+	}
+
+	info!("shutting down");
+	// Stop accepting connections while instances save.
+	drop(listener);
+	for client in clients.values_mut() {
+		if let Err(msg) = client.shut_down().await {
+			error!("failed to notify client of shutdown: {msg}");
+		}
+	}
+	shutdown.send_replace(true);
+	for (i, slot) in instances.iter_mut().enumerate() {
+		let Some(saved) = slot.take().and_then(|x| x.join(i)) else {
+			continue;
+		};
+		if let Some(saves) = &cli.saves {
+			match save(saves, i, &saved) {
+				Ok(()) => info!(instance = i, name = saved.name, "saved instance"),
+				Err(msg) => error!(instance = i, "failed to save instance: {msg:?}"),
+			}
+		} else {
+			warn!(
+				instance = i,
+				name = saved.name,
+				"discarding unfinished run; pass --saves to keep it"
+			);
+		}
 	}
+	Ok(())
 }
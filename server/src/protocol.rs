@@ -9,14 +9,22 @@
 //! Packets should be serializable and deserializable using `rkyv`,
 //! assuming both parties are using the same version.
 //!
+//! Clients begin every connection with a [`ClientPacket::Handshake`].
+//! The server either rejects it ([`ServerPacket::Rejected`]),
+//! or sends any resource modules the client is missing ([`ServerPacket::ModuleFile`])
+//! followed by [`ServerPacket::Accepted`].
+//!
+//! Both sides ping each other every [`HEARTBEAT_INTERVAL`],
+//! and servers close connections that stay silent for longer than [`TIMEOUT`].
+//!
 //! For more information about `rkyv`'s data format: [https://rkyv.org/](https://rkyv.org/)
 
 use esprit2::prelude::*;
 use percent_encoding::percent_decode_str;
 use rkyv::rancor::ResultExt;
 use rkyv::{rancor, util::AlignedVec};
-use std::num::IntErrorKind;
-use std::{io, num::ParseIntError, str::Utf8Error};
+use std::time::Duration;
+use std::{io, str::Utf8Error};
 use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
 use tokio::net::ToSocketAddrs;
 use tokio::sync::mpsc;
@@ -32,6 +40,21 @@ pub type ClientIdentifier = u64;
 /// `(character - 'a') % 10`
 pub const DEFAULT_PORT: u16 = 48578;
 
+/// Incremented whenever packets change in an incompatible way.
+pub const VERSION: u32 = 1;
+
+/// Chat messages are cut off after this many characters.
+pub const MAX_CHAT_LENGTH: usize = 256;
+
+/// How often each side pings the other.
+///
+/// Pings are answered with pongs, which is how round-trip latency is measured.
+pub const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(5);
+/// Connections which haven't sent anything for this long are considered dead.
+///
+/// Heartbeats keep idle connections from reaching this.
+pub const TIMEOUT: Duration = Duration::from_secs(30);
+
 pub type Checksum = u64;
 
 pub fn checksum(bytes: impl Iterator<Item = u8>) -> Checksum {
@@ -43,15 +66,220 @@ pub fn checksum(bytes: impl Iterator<Item = u8>) -> Checksum {
 		.unwrap_or(0)
 }
 
+/// Checksums the parts of a world which clients are expected to predict.
+///
+/// The world's serialized form can't be used directly
+/// because hash maps are serialized in an arbitrary order.
+pub fn world_checksum(world: &world::Manager) -> Checksum {
+	let mut bytes = Vec::new();
+	bytes.extend(world.location.floor.to_le_bytes());
+	bytes.extend(world.defeated.to_le_bytes());
+	bytes.extend(world.random_seed.to_le_bytes());
+	bytes.push(match world.outcome {
+		None => 0,
+		Some(world::Outcome::Victory) => 1,
+		Some(world::Outcome::Defeat) => 2,
+	});
+	for character in &world.characters {
+		let character = character.borrow();
+		bytes.extend(character.sheet.id.as_bytes());
+		for i in [character.x, character.y, character.hp, character.sp] {
+			bytes.extend(i.to_le_bytes());
+		}
+		for i in [character.level, character.experience] {
+			bytes.extend(i.to_le_bytes());
+		}
+		bytes.extend(character.action_delay.to_le_bytes());
+		let mut components = character.components.iter().collect::<Vec<_>>();
+		components.sort_by(|a, b| a.0.cmp(b.0));
+		for (id, value) in components {
+			bytes.extend(id.as_bytes());
+			checksum_value(&mut bytes, value);
+			match character.durations.get(id) {
+				None => bytes.push(0),
+				Some(component::Duration::Forever) => bytes.push(1),
+				Some(component::Duration::Aut(aut)) => {
+					bytes.push(2);
+					bytes.extend(aut.to_le_bytes());
+				}
+				Some(component::Duration::Rest) => bytes.push(3),
+			}
+		}
+	}
+	for item in &world.items {
+		bytes.extend(item.item.name.as_bytes());
+		bytes.extend(item.x.to_le_bytes());
+		bytes.extend(item.y.to_le_bytes());
+	}
+	// Pad the final chunk so that no bytes are ignored.
+	bytes.resize(bytes.len().next_multiple_of(8), 0);
+	checksum(bytes.into_iter())
+}
+
+/// Appends a canonical encoding of a component value to `bytes`.
+///
+/// Table entries are sorted by their encoding,
+/// since lua makes no guarantees about the order they were collected in.
+fn checksum_value(bytes: &mut Vec<u8>, value: &Value) {
+	match value {
+		Value::Unit => bytes.push(0),
+		Value::Boolean(b) => bytes.extend([1, u8::from(*b)]),
+		Value::Integer(i) => {
+			bytes.push(2);
+			bytes.extend(i.to_le_bytes());
+		}
+		Value::Number(n) => {
+			bytes.push(3);
+			bytes.extend(n.to_bits().to_le_bytes());
+		}
+		Value::String(s) => {
+			bytes.push(4);
+			bytes.extend((s.len() as u64).to_le_bytes());
+			bytes.extend(s.as_bytes());
+		}
+		Value::Table(entries) => {
+			let mut entries = entries
+				.iter()
+				.map(|(key, value)| {
+					let mut entry = Vec::new();
+					checksum_value(&mut entry, key);
+					checksum_value(&mut entry, value);
+					entry
+				})
+				.collect::<Vec<_>>();
+			entries.sort();
+			bytes.push(5);
+			bytes.extend((entries.len() as u64).to_le_bytes());
+			bytes.extend(entries.into_iter().flatten());
+		}
+		Value::OrderedTable(values) => {
+			bytes.push(6);
+			bytes.extend((values.len() as u64).to_le_bytes());
+			for value in values {
+				checksum_value(bytes, value);
+			}
+		}
+	}
+}
+
+/// Sent by clients before any other packet, so that mismatched builds can be turned away
+/// with a meaningful error rather than failing to read each other's packets.
+#[derive(Clone, Debug, rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
+pub struct ClientHandshake {
+	/// Always [`VERSION`] for the client's build.
+	pub protocol_version: u32,
+	/// Always [`esprit2::VERSION`] for the client's build.
+	pub engine_version: Box<str>,
+	/// Resource modules available to the client, whether or not it has loaded them.
+	///
+	/// The server sends the files of any modules missing from this list.
+	pub modules: Vec<replay::Module>,
+}
+
+impl ClientHandshake {
+	pub fn new(modules: Vec<replay::Module>) -> Self {
+		Self {
+			protocol_version: VERSION,
+			engine_version: esprit2::VERSION.into(),
+			modules,
+		}
+	}
+
+	/// Compares the client's versions against the server's own.
+	///
+	/// # Errors
+	///
+	/// Returns the first mismatch found.
+	pub fn check(&self) -> Result<(), HandshakeError> {
+		if self.protocol_version != VERSION {
+			return Err(HandshakeError::ProtocolVersion {
+				client: self.protocol_version,
+				server: VERSION,
+			});
+		}
+		if *self.engine_version != *esprit2::VERSION {
+			return Err(HandshakeError::EngineVersion {
+				client: self.engine_version.clone(),
+				server: esprit2::VERSION.into(),
+			});
+		}
+		Ok(())
+	}
+
+	/// Returns every module in `modules` which the client doesn't have.
+	pub fn missing_modules<'a>(
+		&'a self,
+		modules: &'a [replay::Module],
+	) -> impl Iterator<Item = &'a replay::Module> + 'a {
+		modules.iter().filter(|x| !self.modules.contains(x))
+	}
+}
+
+#[derive(Clone, Debug, thiserror::Error, rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
+pub enum HandshakeError {
+	#[error("client uses protocol version {client}, but the server uses version {server}")]
+	ProtocolVersion { client: u32, server: u32 },
+	#[error("client runs engine version {client}, but the server runs version {server}")]
+	EngineVersion { client: Box<str>, server: Box<str> },
+	#[error("client did not send a handshake")]
+	Missing,
+}
+
 #[derive(Clone, Debug, rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
 pub struct ClientAuthentication {
 	pub username: String,
+	/// Only checked by servers with an account store;
+	/// see [`crate::accounts`].
+	pub password: Option<Box<str>>,
+}
+
+#[derive(Clone, Debug, thiserror::Error, rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
+pub enum AuthenticationError {
+	#[error("incorrect username or password")]
+	InvalidCredentials,
+	#[error("this server requires an account; authenticate before joining")]
+	Unauthenticated,
+	#[error("incorrect instance password")]
+	InstancePassword,
+}
+
+#[derive(Clone, Debug, rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
+pub enum InstanceTarget {
+	Id(u32),
+	/// Creates a new instance with this name (protected by the routing's password)
+	/// if no instance has it yet.
+	Name(Box<str>),
 }
 
 #[derive(Clone, Debug, rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
 pub struct ClientRouting {
-	pub instance_id: u32,
+	pub instance: InstanceTarget,
 	pub instance_password: Option<Box<str>>,
+	/// Watch the instance without taking part.
+	///
+	/// Spectators are sent the world and its messages, but their actions are rejected.
+	/// Spectating never creates an instance.
+	pub spectate: bool,
+}
+
+/// An entry in a router's instance directory.
+#[derive(Clone, Debug, rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
+pub struct InstanceInfo {
+	pub id: u32,
+	pub name: Box<str>,
+	pub players: u32,
+	/// Whether joining requires an instance password.
+	pub password: bool,
+}
+
+#[derive(Clone, Debug, thiserror::Error, rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
+pub enum RoutingError {
+	#[error("no such instance")]
+	NoSuchInstance,
+	#[error("the server can't run any more instances")]
+	Full,
+	#[error("join an instance first")]
+	NotRouted,
 }
 
 #[derive(Debug, thiserror::Error)]
@@ -63,26 +291,31 @@ pub enum ClientRoutingError {
 	#[error("missing instance")]
 	MissingInstance,
 	#[error("malformed instance: {0}")]
-	MalformedInstance(#[from] ParseIntError),
+	MalformedInstance(Utf8Error),
 	#[error("malformed password: {0}")]
 	MalformedPassword(#[from] Utf8Error),
 }
 
 impl ClientRouting {
+	/// Parses urls in the form `esprit://[:password@]host[:port][/instance[/instance_password]][?spectate]`,
+	/// where `instance` is either an instance's id or its name.
+	///
+	/// Returns no routing if the url doesn't name an instance.
 	pub fn new(url: &str) -> Result<(Option<Self>, impl ToSocketAddrs), ClientRoutingError> {
 		use ClientRoutingError as E;
 		let url = Url::parse(url)?;
 		let s = url
 			.path_segments()
 			.and_then(|mut segments| {
-				let instance_id = match segments.next()?.parse::<u32>() {
-					Ok(i) => i,
-					Err(e) => {
-						return match e.kind() {
-							IntErrorKind::Empty => None,
-							_ => Some(Err(E::MalformedInstance(e))),
-						}
-					}
+				let instance = match segments.next()? {
+					"" => return None,
+					instance => match instance.parse::<u32>() {
+						Ok(i) => InstanceTarget::Id(i),
+						Err(_) => match percent_decode_str(instance).decode_utf8() {
+							Ok(name) => InstanceTarget::Name(name.into()),
+							Err(e) => return Some(Err(E::MalformedInstance(e))),
+						},
+					},
 				};
 				let instance_password = match segments
 					.next()
@@ -94,8 +327,9 @@ impl ClientRouting {
 				}
 				.map(|x| x.into());
 				Some(Ok(Self {
-					instance_id,
+					instance,
 					instance_password,
+					spectate: url.query_pairs().any(|(key, _)| key == "spectate"),
 				}))
 			})
 			.transpose()?;
@@ -109,29 +343,102 @@ impl ClientRouting {
 			),
 		))
 	}
+
+	/// Returns the account password given in a url's user information, if any.
+	pub fn account_password(url: &str) -> Result<Option<Box<str>>, ClientRoutingError> {
+		Ok(Url::parse(url)?
+			.password()
+			.map(|x| percent_decode_str(x).decode_utf8())
+			.transpose()?
+			.map(|x| x.into()))
+	}
 }
 
 #[derive(Clone, Debug, rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
 pub enum ClientPacket {
+	// Handshake packets
+	// These must remain first so that their representation is shared across versions.
+	Handshake(ClientHandshake),
 	// Generic packets
+	/// Answered with [`ServerPacket::Pong`].
 	Ping,
+	/// Answers a [`ServerPacket::Ping`].
+	Pong,
 	// Root packets
 	Authenticate(ClientAuthentication),
 	Route(ClientRouting),
+	/// Creates a new instance without a name or password.
 	Instantiate,
+	/// Asks for a list of running instances, answered by [`ServerPacket::Instances`].
+	ListInstances,
 	// Instance packets
-	Action { action: character::Action },
+	/// `sequence` is echoed back in [`ServerPacket::Acknowledge`].
+	Action {
+		sequence: u32,
+		action: character::Action,
+	},
+	/// Asks for a fresh copy of the world, discarding the client's prediction.
+	RequestWorld,
+	/// Broadcast to every client in the instance (including the sender)
+	/// as a [`console::MessagePrinter::Chat`] message.
+	///
+	/// Longer than [`MAX_CHAT_LENGTH`] characters is cut short.
+	Chat(Box<str>),
 }
 
 #[derive(Clone, Debug, rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
 pub enum ServerPacket<'a> {
+	/// Sent in response to a failed handshake, after which the client is ignored.
+	///
+	/// This must remain first so that its representation is shared across versions.
+	Rejected(#[rkyv(with = rkyv::with::Inline)] &'a HandshakeError),
+	/// One file of a module the client is missing, sent during the handshake.
+	ModuleFile {
+		#[rkyv(with = rkyv::with::Inline)]
+		module: &'a replay::Module,
+		/// Relative to the module's directory, separated by forward slashes.
+		path: Box<str>,
+		contents: Vec<u8>,
+	},
+	/// Sent once the handshake is complete, after any [`ServerPacket::ModuleFile`]s.
+	///
+	/// Clients should load exactly these modules.
+	Accepted {
+		#[rkyv(with = rkyv::with::Inline)]
+		modules: &'a Vec<replay::Module>,
+	},
+	/// Sent in response to failed authentication, or an incorrect instance password.
+	AuthenticationFailed(#[rkyv(with = rkyv::with::Inline)] &'a AuthenticationError),
+	RoutingFailed(#[rkyv(with = rkyv::with::Inline)] &'a RoutingError),
+	Instances(Vec<InstanceInfo>),
+	/// Sent every [`HEARTBEAT_INTERVAL`], and answered with [`ClientPacket::Pong`].
 	Ping,
+	/// Answers a [`ClientPacket::Ping`].
+	Pong,
 	Register(ClientIdentifier),
 	World {
 		#[rkyv(with = rkyv::with::Inline)]
 		world: &'a world::Manager,
 	},
 	Message(#[rkyv(with = rkyv::with::Inline)] &'a console::Message),
+	/// Sent once an action and every automatic action following it have been performed.
+	///
+	/// `checksum` is the [`world_checksum`] of the resulting world,
+	/// which clients compare against their prediction.
+	/// `messages` are the console messages printed along the way;
+	/// the acting client receives them here instead of as [`ServerPacket::Message`]s,
+	/// since it has already printed its own predicted versions.
+	Acknowledge {
+		sequence: u32,
+		checksum: Checksum,
+		messages: Vec<console::Message>,
+	},
+	/// Sent once the run is over; no further actions will be accepted.
+	RunEnded(#[rkyv(with = rkyv::with::Inline)] &'a world::Summary),
+	/// Sent before the server closes the connection because it is shutting down.
+	///
+	/// Routers may save unfinished runs, which can be rejoined once the server is back.
+	ShuttingDown,
 }
 
 #[derive(Debug)]
@@ -139,6 +446,14 @@ pub struct PacketReceiver {
 	pub task: task::JoinHandle<io::Result<()>>,
 }
 
+impl Drop for PacketReceiver {
+	fn drop(&mut self) {
+		// The task would otherwise keep the connection open until the peer sends something,
+		// which a half-open connection never will.
+		self.task.abort();
+	}
+}
+
 impl PacketReceiver {
 	pub fn new(read: OwnedReadHalf) -> (Self, mpsc::Receiver<AlignedVec>) {
 		let (send, channel) = mpsc::channel::<AlignedVec>(8);
@@ -1,7 +1,7 @@
 use crate::prelude::*;
 use mlua::IntoLuaMulti;
 use rkyv::with::{ArchiveWith, DeserializeWith, SerializeWith};
-use std::cell::RefCell;
+use std::cell::{OnceCell, RefCell};
 use std::collections::HashMap;
 use std::rc::Rc;
 
@@ -54,6 +54,220 @@ impl Ref {
 	pub fn new(character: character::Piece) -> Self {
 		Self(Rc::new(InnerRef(RefCell::new(character))))
 	}
+
+	/// Constructs a fully-formed piece from a sheet.
+	///
+	/// The sheet's initial components are checked against their schemas
+	/// and inserted without calling `on_attach`,
+	/// after which its prototype (if any) is given the piece to finish building it.
+	pub fn from_sheet(resources: &resource::Manager, sheet: &Sheet) -> mlua::Result<Self> {
+		for (component_id, value) in &sheet.components {
+			let component = resources
+				.component
+				.get(component_id)
+				.map_err(mlua::Error::external)?;
+			if let Some(schema) = &component.schema {
+				schema.validate(value).map_err(|e| {
+					mlua::Error::runtime(format!(
+						"invalid value for component {component_id} in sheet {}: {e}",
+						sheet.id
+					))
+				})?;
+			}
+		}
+		let piece = Self::new(Piece::new(sheet.clone()));
+		if let Some(prototype) = &sheet.prototype {
+			prototype.call::<()>(piece.clone())?;
+		}
+		Ok(piece)
+	}
+
+	/// Adds a component to the piece, calling its `on_attach` hook.
+	///
+	/// Without an explicit `duration`, a component which is already attached keeps its remaining time,
+	/// and a newly attached one uses the component's default duration.
+	pub fn attach(
+		&self,
+		resources: &resource::Manager,
+		component_id: Box<str>,
+		value: Value,
+		duration: Option<component::Duration>,
+	) -> mlua::Result<()> {
+		let component = resources
+			.component
+			.get(&component_id)
+			.map_err(mlua::Error::external)?;
+		if let Some(schema) = &component.schema {
+			schema.validate(&value).map_err(|e| {
+				mlua::Error::runtime(format!("invalid value for component {component_id}: {e}"))
+			})?;
+		}
+		let previous = {
+			let mut piece = self.borrow_mut();
+			piece.invalidate_stats();
+			let duration = duration.or(if piece.components.contains_key(&component_id) {
+				None
+			} else {
+				component.duration
+			});
+			match duration {
+				Some(component::Duration::Forever) => {
+					piece.durations.remove(&component_id);
+				}
+				Some(duration) => {
+					piece.durations.insert(component_id.clone(), duration);
+				}
+				None => {}
+			}
+			piece.components.insert(component_id, value)
+		};
+		if let Some(on_attach) = &component.on_attach {
+			on_attach.call::<()>((self.clone(), previous))?;
+		}
+		Ok(())
+	}
+
+	/// Removes a component from the piece, calling its `on_detach` hook.
+	pub fn detach(
+		&self,
+		resources: &resource::Manager,
+		component_id: &str,
+		annotation: mlua::Value,
+	) -> mlua::Result<()> {
+		let component = resources
+			.component
+			.get(component_id)
+			.map_err(mlua::Error::external)?;
+		let previous = {
+			let mut piece = self.borrow_mut();
+			piece.invalidate_stats();
+			piece.durations.remove(component_id);
+			piece.components.remove(component_id)
+		};
+		if let Some(on_detach) = &component.on_detach {
+			on_detach.call::<()>((self.clone(), previous, annotation))?;
+		}
+		Ok(())
+	}
+
+	/// Counts down the duration of every timed component, expiring those which run out.
+	pub fn elapse(&self, resources: &resource::Manager, time: Aut) -> mlua::Result<()> {
+		let expired = {
+			let mut piece = self.borrow_mut();
+			let mut expired = Vec::new();
+			for (component_id, duration) in &mut piece.durations {
+				if let component::Duration::Aut(remaining) = duration {
+					*remaining = remaining.saturating_sub(time);
+					if *remaining == 0 {
+						expired.push(component_id.clone());
+					}
+				}
+			}
+			expired
+		};
+		self.expire_all(resources, expired)
+	}
+
+	/// Expires every component which lasts until the piece rests.
+	pub fn rest(&self, resources: &resource::Manager) -> mlua::Result<()> {
+		let expired = self
+			.borrow()
+			.durations
+			.iter()
+			.filter(|(_, duration)| **duration == component::Duration::Rest)
+			.map(|(component_id, _)| component_id.clone())
+			.collect();
+		self.expire_all(resources, expired)
+	}
+
+	fn expire_all(
+		&self,
+		resources: &resource::Manager,
+		mut expired: Vec<Box<str>>,
+	) -> mlua::Result<()> {
+		// Expire components in a consistent order, regardless of how they were hashed.
+		expired.sort();
+		for component_id in expired {
+			self.expire(resources, &component_id)?;
+		}
+		Ok(())
+	}
+
+	/// Calls a component's `on_expire` hook and detaches it,
+	/// unless the hook gave the component a new duration.
+	pub fn expire(&self, resources: &resource::Manager, component_id: &str) -> mlua::Result<()> {
+		let component = resources
+			.component
+			.get(component_id)
+			.map_err(mlua::Error::external)?;
+		let value = {
+			let mut piece = self.borrow_mut();
+			piece.durations.remove(component_id);
+			piece.components.get(component_id).cloned()
+		};
+		// The component may have been detached by an earlier expiry.
+		let Some(value) = value else {
+			return Ok(());
+		};
+		if let Some(on_expire) = &component.on_expire {
+			on_expire.call::<()>((self.clone(), value))?;
+		}
+		if !self.borrow().durations.contains_key(component_id) {
+			self.detach(resources, component_id, mlua::Nil)?;
+		}
+		Ok(())
+	}
+
+	/// Adds experience to the piece, levelling it up as many times as its growth table allows.
+	///
+	/// Returns how many levels were gained.
+	pub fn gain_experience(&self, amount: u32) -> u32 {
+		let mut levels = 0;
+		let experience = {
+			let mut piece = self.borrow_mut();
+			piece.experience = piece.experience.saturating_add(amount);
+			piece.experience
+		};
+		while self
+			.next_growth()
+			.is_some_and(|growth| growth.experience <= experience)
+		{
+			self.level_up();
+			levels += 1;
+		}
+		levels
+	}
+
+	/// Applies the next entry of the piece's growth table, regardless of its experience.
+	///
+	/// Health and soul are increased alongside their maximums.
+	/// Returns `false` if the piece has no growth left.
+	pub fn level_up(&self) -> bool {
+		let Some(growth) = self.next_growth() else {
+			return false;
+		};
+		let mut piece = self.borrow_mut();
+		piece.level += 1;
+		piece.sheet.stats = piece.sheet.stats + growth.stats;
+		piece.invalidate_stats();
+		piece.hp += growth.stats.heart as i32;
+		piece.sp += growth.stats.soul as i32;
+		for ability in growth.abilities {
+			if !piece.sheet.abilities.contains(&ability) {
+				piece.sheet.abilities.push(ability);
+			}
+		}
+		true
+	}
+
+	fn next_growth(&self) -> Option<Growth> {
+		let piece = self.borrow();
+		piece
+			.sheet
+			.growth
+			.get(piece.level.saturating_sub(1) as usize)
+			.cloned()
+	}
 }
 
 impl PartialEq for Ref {
@@ -97,7 +311,7 @@ impl mlua::UserData for Ref {
 		fields.add_field_method_get("stats", |lua, this| {
 			this.borrow().stats(lua).map_err(mlua::Error::runtime)
 		});
-		get!(hp, sp, x, y);
+		get!(hp, sp, x, y, level, experience);
 		set!(hp, sp, x, y);
 	}
 
@@ -120,6 +334,11 @@ impl mlua::UserData for Ref {
 			}
 		});
 
+		methods.add_method("level_up", |_, this, ()| Ok(this.level_up()));
+		methods.add_method("gain_experience", |_, this, amount: u32| {
+			Ok(this.gain_experience(amount))
+		});
+
 		methods.add_method("replace_nouns", |_, this, s: String| {
 			Ok(s.replace_nouns(&this.borrow().sheet.nouns))
 		});
@@ -131,21 +350,15 @@ impl mlua::UserData for Ref {
 		);
 		methods.add_method(
 			"attach",
-			|lua, this, (component_id, value): (Box<str>, Value)| {
+			|lua,
+			 this,
+			 (component_id, value, duration): (Box<str>, Value, Option<component::Duration>)| {
 				let resources = lua
 					.globals()
 					.get::<mlua::Table>("package")?
 					.get::<mlua::Table>("loaded")?
 					.get::<resource::Handle>("runtime.resources")?;
-				let component = resources
-					.component
-					.get(&component_id)
-					.map_err(mlua::Error::external)?;
-				let previous = this.borrow_mut().components.insert(component_id, value);
-				if let Some(on_attach) = &component.on_attach {
-					on_attach.call::<()>((this.clone(), previous))?;
-				}
-				Ok(())
+				this.attach(&resources, component_id, value, duration)
 			},
 		);
 		methods.add_method("component", |lua, this, component_id: mlua::String| {
@@ -163,16 +376,7 @@ impl mlua::UserData for Ref {
 					.get::<mlua::Table>("package")?
 					.get::<mlua::Table>("loaded")?
 					.get::<resource::Handle>("runtime.resources")?;
-				let component_id = component_id.to_str()?;
-				let component = resources
-					.component
-					.get(component_id.as_ref())
-					.map_err(mlua::Error::external)?;
-				let previous = this.borrow_mut().components.remove(component_id.as_ref());
-				if let Some(on_detach) = &component.on_detach {
-					on_detach.call::<()>((this.clone(), previous, annotation))?;
-				}
-				Ok(())
+				this.detach(&resources, &component_id.to_str()?, annotation)
 			},
 		)
 	}
@@ -208,6 +412,13 @@ pub struct Piece {
 
 	/// Additional components of the piece with optional data.
 	pub components: HashMap<Box<str>, Value>,
+	/// Remaining time for components which don't last forever.
+	pub durations: HashMap<Box<str>, component::Duration>,
+
+	/// Starts at 1, and increases as the piece's growth table is applied.
+	pub level: u32,
+	/// Experience earned by defeating other pieces.
+	pub experience: u32,
 
 	/// How much time has to pass until the piece is allowed to take an action.
 	///
@@ -215,6 +426,17 @@ pub struct Piece {
 	/// but in the event that one shoudn't, a consideration script which always skips the
 	/// piece's turn should be sufficient.
 	pub action_delay: Aut,
+
+	/// The piece which brought this one's health to 0, until the world processes its death.
+	#[rkyv(with = rkyv::with::Skip)]
+	pub killer: Option<Ref>,
+
+	/// Result of the last `stat_outcomes` call, since computing it calls into every component.
+	///
+	/// Only depends on `sheet.stats` and `components`;
+	/// see [`Piece::invalidate_stats`].
+	#[rkyv(with = rkyv::with::Skip)]
+	stat_cache: OnceCell<StatOutcomes>,
 }
 
 // Don't add stupid methods to this!
@@ -224,75 +446,130 @@ impl Piece {
 		let hp = sheet.stats.heart as i32;
 		let sp = sheet.stats.soul as i32;
 
+		let components = sheet.components.clone();
+
 		Self {
 			sheet,
 			hp,
 			sp,
-			components: HashMap::new(),
+			components,
+			durations: HashMap::new(),
+			level: 1,
+			experience: 0,
 			x: 0,
 			y: 0,
 			action_delay: 0,
+			killer: None,
+			stat_cache: OnceCell::new(),
 		}
 	}
+
+	/// Discards the piece's cached stats.
+	///
+	/// `attach` and `detach` do this automatically,
+	/// but anything else which modifies `components` or `sheet.stats` must call it.
+	/// Built-in components have no stat modifiers, so they're exempt.
+	pub fn invalidate_stats(&mut self) {
+		self.stat_cache.take();
+	}
 }
 
 #[derive(Clone, Debug, Default)]
 pub struct StatOutcomes {
 	pub stats: Stats,
+	/// Sum of every flat bonus.
 	pub buffs: Stats,
+	/// Sum of every flat deduction.
 	pub debuffs: Stats,
+	/// Sum of every percentage bonus.
+	pub buff_percent: Stats,
+	/// Sum of every percentage deduction.
+	pub debuff_percent: Stats,
+	/// Every component which contributed to the outcome, sorted by id.
+	pub sources: Vec<Modifier>,
+}
+
+/// The stat modifiers contributed by a single component.
+#[derive(Clone, Debug, Default)]
+pub struct Modifier {
+	pub component: Box<str>,
+	pub buff: Stats,
+	pub buff_percent: Stats,
+	pub debuff: Stats,
+	pub debuff_percent: Stats,
 }
 
 impl Piece {
 	pub fn stats(&self, lua: &mlua::Lua) -> mlua::Result<Stats> {
-		self.stat_outcomes(lua).map(|x| x.stats)
+		self.stat_cache
+			.get_or_try_init(|| self.compute_stat_outcomes(lua))
+			.map(|x| x.stats)
 	}
 
+	/// Flat modifiers are applied to the sheet's stats first,
+	/// followed by the sum of all percentage modifiers.
 	pub fn stat_outcomes(&self, lua: &mlua::Lua) -> mlua::Result<StatOutcomes> {
-		let buffs = Stats::default();
-		let mut debuffs = Stats::default();
+		self.stat_cache
+			.get_or_try_init(|| self.compute_stat_outcomes(lua))
+			.cloned()
+	}
+
+	fn compute_stat_outcomes(&self, lua: &mlua::Lua) -> mlua::Result<StatOutcomes> {
 		let resources: resource::Handle =
 			lua.load(mlua::chunk!(require "runtime.resources")).eval()?;
 
+		let mut sources = Vec::new();
 		for (component_id, value) in &self.components {
-			if let Ok(component) = resources.component.get(component_id.as_ref())
-				&& let Some(on_debuff) = &component.on_debuff
-			{
-				let debuff = on_debuff.call(value.as_lua(lua)?)?;
-				debuffs = debuffs + debuff;
+			let Ok(component) = resources.component.get(component_id.as_ref()) else {
+				continue;
+			};
+			if component.on_buff.is_none() && component.on_debuff.is_none() {
+				continue;
 			}
+			let call = |hook: &Option<mlua::Function>| -> mlua::Result<(Stats, Stats)> {
+				let Some(hook) = hook else {
+					return Ok((Stats::default(), Stats::default()));
+				};
+				let (flat, percent): (Option<Stats>, Option<Stats>) =
+					hook.call(value.as_lua(lua)?)?;
+				Ok((flat.unwrap_or_default(), percent.unwrap_or_default()))
+			};
+			let (buff, buff_percent) = call(&component.on_buff)?;
+			let (debuff, debuff_percent) = call(&component.on_debuff)?;
+			sources.push(Modifier {
+				component: component_id.clone(),
+				buff,
+				buff_percent,
+				debuff,
+				debuff_percent,
+			});
 		}
-
-		let mut stats = self.sheet.stats;
-		stats.heart = stats
-			.heart
-			.saturating_sub(debuffs.heart)
-			.saturating_add(buffs.heart);
-		stats.soul = stats
-			.soul
-			.saturating_sub(debuffs.soul)
-			.saturating_add(buffs.soul);
-		stats.power = stats
-			.power
-			.saturating_sub(debuffs.power)
-			.saturating_add(buffs.power);
-		stats.defense = stats
-			.defense
-			.saturating_sub(debuffs.defense)
-			.saturating_add(buffs.defense);
-		stats.magic = stats
-			.magic
-			.saturating_sub(debuffs.magic)
-			.saturating_add(buffs.magic);
-		stats.resistance = stats
-			.resistance
-			.saturating_sub(debuffs.resistance)
-			.saturating_add(buffs.resistance);
+		sources.sort_by(|a, b| a.component.cmp(&b.component));
+
+		let sum = |f: fn(&Modifier) -> Stats| {
+			sources
+				.iter()
+				.map(f)
+				.fold(Stats::default(), |sum, x| sum + x)
+		};
+		let buffs = sum(|x| x.buff);
+		let debuffs = sum(|x| x.debuff);
+		let buff_percent = sum(|x| x.buff_percent);
+		let debuff_percent = sum(|x| x.debuff_percent);
+
+		let multiplier = Stats::default().map(|_| 100) + buff_percent - debuff_percent;
+		let stats = (self.sheet.stats + buffs - debuffs)
+			.zip_with(multiplier, |stat, multiplier| {
+				(stat as u32 * multiplier as u32 / 100).min(u16::MAX as u32) as u16
+			});
 
 		Ok(StatOutcomes {
 			stats,
 			buffs,
 			debuffs,
+			buff_percent,
+			debuff_percent,
+			sources,
 		})
 	}
 }
@@ -302,7 +579,9 @@ impl Piece {
 /// This is the only way that character logic or player input should communicate with pieces.
 /// The information here should be enough to perform the action, but in the event it isn't
 /// (from an incomplete player input), an `ActionRequest` will be yielded to fill in the missing information.
-#[derive(Clone, Debug, rkyv::Archive, rkyv::Serialize, rkyv::Deserialize, mlua::FromLua)]
+#[derive(
+	Clone, Debug, PartialEq, rkyv::Archive, rkyv::Serialize, rkyv::Deserialize, mlua::FromLua,
+)]
 pub enum Action {
 	Move(i32, i32),
 	Ability(Box<str>, Value),
@@ -324,10 +603,41 @@ pub struct Sheet {
 
 	pub abilities: Vec<Box<str>>,
 
+	/// What the piece gains each time it levels up.
+	///
+	/// The first entry is applied when reaching level 2, the second for level 3, and so on.
+	/// A piece which has used up its growth table can't level up any further.
+	pub growth: Vec<Growth>,
+
+	/// Components every piece made from this sheet starts with.
+	pub components: HashMap<Box<str>, Value>,
+	/// Called with each newly constructed piece, after its initial components are in place.
+	///
+	/// This is only meaningful wherever the sheet's resources were loaded,
+	/// so it isn't archived.
+	#[rkyv(with = rkyv::with::Skip)]
+	pub prototype: Option<mlua::Function>,
+
+	/// Experience awarded to each standing party member for defeating this piece.
+	pub experience: u32,
+	/// Whether defeating this piece leaves a corpse behind.
+	pub corpse: bool,
+
 	/// Script to decide on an action from a list of considerations
 	pub on_consider: Box<str>,
 }
 
+/// A single level's worth of growth.
+#[derive(Clone, Debug, Default, rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
+pub struct Growth {
+	/// Total experience required to reach this level.
+	pub experience: u32,
+	/// Added to the piece's base stats.
+	pub stats: Stats,
+	/// Abilities learned upon reaching this level.
+	pub abilities: Vec<Box<str>>,
+}
+
 #[derive(
 	Clone, Copy, Debug, Default, mlua::FromLua, rkyv::Archive, rkyv::Serialize, rkyv::Deserialize,
 )]
@@ -347,18 +657,39 @@ pub struct Stats {
 	pub resistance: u16,
 }
 
+impl Stats {
+	/// Applies a function to each stat.
+	pub fn map(self, f: impl Fn(u16) -> u16) -> Self {
+		Stats {
+			heart: f(self.heart),
+			soul: f(self.soul),
+			power: f(self.power),
+			defense: f(self.defense),
+			magic: f(self.magic),
+			resistance: f(self.resistance),
+		}
+	}
+
+	/// Combines each stat with its counterpart in `rhs`.
+	pub fn zip_with(self, rhs: Self, f: impl Fn(u16, u16) -> u16) -> Self {
+		Stats {
+			heart: f(self.heart, rhs.heart),
+			soul: f(self.soul, rhs.soul),
+			power: f(self.power, rhs.power),
+			defense: f(self.defense, rhs.defense),
+			magic: f(self.magic, rhs.magic),
+			resistance: f(self.resistance, rhs.resistance),
+		}
+	}
+}
+
+// All arithmetic saturates, since stats can't be negative and modifiers stack without limit.
+
 impl std::ops::Add for Stats {
 	type Output = Stats;
 
 	fn add(self, rhs: Self) -> Self {
-		Stats {
-			heart: self.heart + rhs.heart,
-			soul: self.soul + rhs.soul,
-			power: self.power + rhs.power,
-			defense: self.defense + rhs.defense,
-			magic: self.magic + rhs.magic,
-			resistance: self.resistance + rhs.resistance,
-		}
+		self.zip_with(rhs, u16::saturating_add)
 	}
 }
 
@@ -366,14 +697,7 @@ impl std::ops::Sub for Stats {
 	type Output = Stats;
 
 	fn sub(self, rhs: Self) -> Self {
-		Stats {
-			heart: self.heart - rhs.heart,
-			soul: self.soul - rhs.soul,
-			power: self.power - rhs.power,
-			defense: self.defense - rhs.defense,
-			magic: self.magic - rhs.magic,
-			resistance: self.resistance - rhs.resistance,
-		}
+		self.zip_with(rhs, u16::saturating_sub)
 	}
 }
 
@@ -381,14 +705,7 @@ impl std::ops::Mul<u16> for Stats {
 	type Output = Stats;
 
 	fn mul(self, rhs: u16) -> Self {
-		Stats {
-			heart: self.heart * rhs,
-			soul: self.soul * rhs,
-			power: self.power * rhs,
-			defense: self.defense * rhs,
-			magic: self.magic * rhs,
-			resistance: self.resistance * rhs,
-		}
+		self.map(|x| x.saturating_mul(rhs))
 	}
 }
 
@@ -396,14 +713,7 @@ impl std::ops::Mul for Stats {
 	type Output = Stats;
 
 	fn mul(self, rhs: Self) -> Self {
-		Stats {
-			heart: self.heart * rhs.heart,
-			soul: self.soul * rhs.soul,
-			power: self.power * rhs.power,
-			defense: self.defense * rhs.defense,
-			magic: self.magic * rhs.magic,
-			resistance: self.resistance * rhs.resistance,
-		}
+		self.zip_with(rhs, u16::saturating_mul)
 	}
 }
 
@@ -411,14 +721,7 @@ impl std::ops::Div<u16> for Stats {
 	type Output = Stats;
 
 	fn div(self, rhs: u16) -> Self {
-		Stats {
-			heart: self.heart / rhs,
-			soul: self.soul / rhs,
-			power: self.power / rhs,
-			defense: self.defense / rhs,
-			magic: self.magic / rhs,
-			resistance: self.resistance / rhs,
-		}
+		self.map(|x| x.saturating_div(rhs))
 	}
 }
 
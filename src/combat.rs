@@ -1,3 +1,5 @@
+use crate::prelude::*;
+use anyhow::Context;
 use std::fmt;
 
 #[derive(Clone, Debug, mlua::FromLua, rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
@@ -12,7 +14,14 @@ pub enum Log {
 	Glance,
 }
 
-impl mlua::UserData for Log {}
+impl mlua::UserData for Log {
+	fn add_methods<M: mlua::UserDataMethods<Self>>(methods: &mut M) {
+		methods.add_method("hit", |_, this, ()| Ok(matches!(this, Log::Hit { .. })));
+		methods.add_method("success", |_, this, ()| Ok(matches!(this, Log::Success)));
+		methods.add_method("miss", |_, this, ()| Ok(matches!(this, Log::Miss)));
+		methods.add_method("glance", |_, this, ()| Ok(matches!(this, Log::Glance)));
+	}
+}
 
 impl fmt::Display for Log {
 	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
@@ -33,3 +42,153 @@ impl Log {
 		}
 	}
 }
+
+/// What sort of harm an attack deals, so that components can resist or amplify it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DamageKind {
+	/// Typically reduced by defense.
+	Physical,
+	/// Typically reduced by resistance.
+	Magical,
+	/// Unaffected by defense or resistance, but still subject to components.
+	Pure,
+}
+
+/// The harm an attack attempts to deal, before the target's defenses are considered.
+#[derive(Clone, Copy, Debug)]
+pub struct Attack {
+	pub magnitude: u32,
+	pub kind: DamageKind,
+	/// Attacks which get past the target's defenses by this much or less only glance off.
+	pub pierce: u32,
+}
+
+/// Deals damage from `user` to `target`, logging and returning the outcome.
+///
+/// Physical attacks are reduced by the target's defense, and magical attacks by its resistance.
+/// The remaining amount is adjusted by the components of both pieces before being applied,
+/// and health is never reduced below 0.
+/// If the target's health reaches 0, `user` is recorded as its killer
+/// and its components' `on_death` hooks are called.
+/// The killer is only kept if the target is still dead afterwards.
+///
+/// `describe` picks the text shown alongside the resulting log.
+pub fn damage(
+	lua: &mlua::Lua,
+	resources: &resource::Manager,
+	console: impl console::Handle,
+	user: &character::Ref,
+	target: &character::Ref,
+	attack: Attack,
+	describe: impl FnOnce(&Log) -> anyhow::Result<Box<str>>,
+) -> anyhow::Result<Log> {
+	let Attack {
+		magnitude,
+		kind,
+		pierce,
+	} = attack;
+	let stats = target.borrow().stats(lua)?;
+	let mut amount = magnitude.saturating_sub(match kind {
+		DamageKind::Physical => stats.defense.into(),
+		DamageKind::Magical => stats.resistance.into(),
+		DamageKind::Pure => 0,
+	});
+	let glanced = amount > 0 && amount <= pierce;
+	if glanced {
+		amount = 0;
+	}
+	for (component_id, on_attack) in component::hooks(resources, user, |c| c.on_attack.as_ref())? {
+		amount = on_attack
+			.call::<Option<mlua::Integer>>((user.clone(), amount, kind, target.clone()))
+			.with_context(|| format!("failed to call on_attack for component {component_id}"))?
+			.map_or(amount, clamp_amount);
+	}
+	for (component_id, on_defend) in component::hooks(resources, target, |c| c.on_defend.as_ref())?
+	{
+		amount = on_defend
+			.call::<Option<mlua::Integer>>((target.clone(), amount, kind, user.clone()))
+			.with_context(|| format!("failed to call on_defend for component {component_id}"))?
+			.map_or(amount, clamp_amount);
+	}
+
+	let (damage, dead) = {
+		let mut target = target.borrow_mut();
+		let previous = target.hp;
+		target.hp = target
+			.hp
+			.saturating_sub_unsigned(amount)
+			.max(0)
+			.min(previous);
+		(
+			(previous - target.hp) as u32,
+			previous > 0 && target.hp <= 0,
+		)
+	};
+	// Log the attack itself before anything its hooks might print.
+	let log = if glanced {
+		Log::Glance
+	} else if damage == 0 {
+		Log::Miss
+	} else {
+		Log::Hit { damage }
+	};
+	console.combat_log(describe(&log)?, log.clone());
+
+	if damage > 0 {
+		component::dispatch(
+			resources,
+			target,
+			"on_damage",
+			|c| c.on_damage.as_ref(),
+			(target.clone(), damage, user.clone()),
+		)?;
+		component::dispatch(
+			resources,
+			user,
+			"on_deal_damage",
+			|c| c.on_deal_damage.as_ref(),
+			(user.clone(), damage, target.clone()),
+		)?;
+	}
+	if dead {
+		target.borrow_mut().killer = Some(user.clone());
+		let result = component::dispatch(
+			resources,
+			target,
+			"on_death",
+			|c| c.on_death.as_ref(),
+			(target.clone(), user.clone()),
+		);
+		// A piece revived by its `on_death` hooks is never processed by `world::Manager`,
+		// so its killer must be forgotten here rather than kept alive (possibly in a cycle).
+		if target.borrow().hp > 0 {
+			target.borrow_mut().killer = None;
+		}
+		result?;
+	}
+	Ok(log)
+}
+
+/// Converts a damage amount from lua, treating negative amounts as 0.
+pub fn clamp_amount(amount: mlua::Integer) -> u32 {
+	amount.clamp(0, u32::MAX as mlua::Integer) as u32
+}
+
+/// Restores up to `amount` health to `target`, without exceeding its maximum.
+///
+/// Downed pieces get back up if this brings their health above 0.
+/// Returns how much health was restored.
+pub fn heal(lua: &mlua::Lua, target: &character::Ref, amount: u32) -> mlua::Result<u32> {
+	let heart = target.borrow().stats(lua)?.heart as i32;
+	let mut target = target.borrow_mut();
+	let previous = target.hp;
+	target.hp = target
+		.hp
+		.saturating_add_unsigned(amount)
+		.min(heart)
+		.max(previous);
+	if target.hp > 0 {
+		target.components.remove(":downed");
+	}
+	Ok((target.hp - previous) as u32)
+}
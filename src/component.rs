@@ -1,9 +1,20 @@
+use crate::prelude::*;
+use anyhow::Context;
+
 #[derive(Clone, Debug)]
 pub struct Component {
 	pub name: String,
 	pub icon: Option<String>,
 	/// If `true`, the component should be displayed to the user on stat screens.
 	pub visible: bool,
+	/// The shape values of this component must have.
+	///
+	/// Components without a schema accept any value.
+	pub schema: Option<Schema>,
+	/// How long the component lasts when attached without an explicit duration.
+	///
+	/// Defaults to forever.
+	pub duration: Option<Duration>,
 
 	/// Called any time the component is attached to a piece.
 	///
@@ -22,14 +33,296 @@ pub struct Component {
 	/// This does nothing, but gets passed down to on_detach.
 	pub on_detach: Option<mlua::Function>,
 
+	/// Called when the component's duration runs out, before it is detached.
+	///
+	/// Recieves the piece and the component's value.
+	/// Attaching the component with a new duration from this function prevents it from being detached.
+	pub on_expire: Option<mlua::Function>,
+
 	/// Called any time a turn is taken.
 	///
 	/// Recieves the piece and the time the turn took as arguments.
 	pub on_turn: Option<mlua::Function>,
-	/// Used to determine any deductions that need to be applied to the piece's stats.
+	/// Called when the piece rests by waiting out its turn.
+	///
+	/// Recieves the piece as an argument.
+	pub on_rest: Option<mlua::Function>,
+	/// Adjusts damage that the piece is about to deal.
+	///
+	/// Recieves the piece, the amount of damage, its kind, and the target.
+	/// Returns the new amount of damage, or nil to leave it unchanged.
+	pub on_attack: Option<mlua::Function>,
+	/// Adjusts damage that the piece is about to take.
+	///
+	/// Recieves the piece, the amount of damage, its kind, and the attacker.
+	/// Returns the new amount of damage, or nil to leave it unchanged.
+	pub on_defend: Option<mlua::Function>,
+	/// Called after the piece loses health to `combat::damage`.
+	///
+	/// Recieves the piece, the amount of health lost, and the piece responsible.
+	pub on_damage: Option<mlua::Function>,
+	/// Called after the piece causes another piece to lose health through `combat::damage`.
+	///
+	/// Recieves the piece, the amount of health lost, and the piece which lost it.
+	pub on_deal_damage: Option<mlua::Function>,
+	/// Called when `combat::damage` reduces the piece's health to 0,
+	/// before it is removed from the board.
+	///
+	/// Recieves the piece and the piece responsible.
+	/// If this restores the piece's health, it is not removed.
+	pub on_death: Option<mlua::Function>,
+	/// Called after the piece moves to a new tile.
+	///
+	/// Recieves the piece and the coordinates it moved from.
+	pub on_move: Option<mlua::Function>,
+	/// Called for each party member after a new floor is generated.
+	///
+	/// Recieves the piece and the depth of the new floor.
+	pub on_floor_change: Option<mlua::Function>,
+	/// Used to determine any bonuses that need to be applied to the piece's stats.
 	///
 	/// Recieves only the component value as an argument, not the piece.
+	/// Returns flat bonuses, followed by optional percentage bonuses
+	/// which are applied after every flat modifier.
+	pub on_buff: Option<mlua::Function>,
+	/// Used to determine any deductions that need to be applied to the piece's stats.
+	///
+	/// Behaves identically to `on_buff`.
 	pub on_debuff: Option<mlua::Function>,
 }
 
 impl mlua::UserData for Component {}
+
+/// How long a component remains attached to a piece.
+///
+/// In lua, a plain integer is interpreted as a number of auts.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
+pub enum Duration {
+	/// Never expires on its own.
+	Forever,
+	/// Expires once this much time has passed.
+	Aut(Aut),
+	/// Expires the next time the piece rests.
+	Rest,
+}
+
+impl mlua::UserData for Duration {}
+
+impl mlua::FromLua for Duration {
+	fn from_lua(value: mlua::Value, _lua: &mlua::Lua) -> mlua::Result<Self> {
+		match value {
+			mlua::Value::Integer(aut) => Ok(Duration::Aut(aut.clamp(0, Aut::MAX as _) as Aut)),
+			mlua::Value::UserData(any) => any.borrow::<Self>().map(|x| *x),
+			_ => Err(mlua::Error::FromLuaConversionError {
+				from: value.type_name(),
+				to: "Duration".into(),
+				message: None,
+			}),
+		}
+	}
+}
+
+/// Describes which values a component accepts.
+///
+/// In lua, scalar schemas are written as their names (`"integer"`),
+/// lists as `{ list = schema }`, and records as `{ record = { field = schema } }`.
+#[derive(Clone, Debug)]
+pub enum Schema {
+	/// Only accepts `nil`, for components which are either present or not.
+	Unit,
+	Boolean,
+	Integer,
+	/// Accepts integers as well.
+	Number,
+	String,
+	/// A sequence of values which all match the inner schema.
+	List(Box<Schema>),
+	/// A table with exactly these fields.
+	Record(Box<[(Box<str>, Schema)]>),
+}
+
+#[derive(Clone, Debug, thiserror::Error)]
+#[error("expected {expected}, found {found}{}", if path.is_empty() { String::new() } else { format!(" at {path}") })]
+pub struct SchemaError {
+	pub expected: String,
+	pub found: &'static str,
+	/// Location of the mismatched value within the component's value, such as `.teams[2]`.
+	pub path: String,
+}
+
+impl Schema {
+	pub fn validate(&self, value: &Value) -> Result<(), SchemaError> {
+		let mismatch = || SchemaError {
+			expected: self.to_string(),
+			found: value_type(value),
+			path: String::new(),
+		};
+		// Empty lua tables are indistinguishable from empty lists,
+		// so only non-empty lists are ruled out as records.
+		let list = matches!(value, Value::OrderedTable(values) if !values.is_empty());
+		match (self, value) {
+			(Schema::Unit, Value::Unit)
+			| (Schema::Boolean, Value::Boolean(_))
+			| (Schema::Integer, Value::Integer(_))
+			| (Schema::Number, Value::Integer(_) | Value::Number(_))
+			| (Schema::String, Value::String(_)) => Ok(()),
+			(Schema::List(schema), Value::OrderedTable(values)) => {
+				for (i, value) in values.iter().enumerate() {
+					schema.validate(value).map_err(|mut e| {
+						e.path.insert_str(0, &format!("[{}]", i + 1));
+						e
+					})?;
+				}
+				Ok(())
+			}
+			(Schema::Record(fields), Value::Table(_) | Value::OrderedTable(_)) if !list => {
+				let values: &[(Value, Value)] = match value {
+					Value::Table(values) => values,
+					_ => &[],
+				};
+				for (key, _) in values {
+					if !matches!(key, Value::String(key) if fields.iter().any(|(field, _)| field == key))
+					{
+						return Err(SchemaError {
+							expected: "no other fields".into(),
+							found: "an unknown field",
+							path: match key {
+								Value::String(key) => format!(".{key}"),
+								_ => format!("[{}]", value_type(key)),
+							},
+						});
+					}
+				}
+				for (field, schema) in fields {
+					let value = values
+						.iter()
+						.find(|(key, _)| matches!(key, Value::String(key) if key == field))
+						.map(|(_, value)| value)
+						.unwrap_or(&Value::Unit);
+					schema.validate(value).map_err(|mut e| {
+						e.path.insert_str(0, &format!(".{field}"));
+						e
+					})?;
+				}
+				Ok(())
+			}
+			_ => Err(mismatch()),
+		}
+	}
+}
+
+fn value_type(value: &Value) -> &'static str {
+	match value {
+		Value::Unit => "nil",
+		Value::Boolean(_) => "boolean",
+		Value::Integer(_) => "integer",
+		Value::Number(_) => "number",
+		Value::String(_) => "string",
+		Value::Table(_) => "table",
+		Value::OrderedTable(_) => "list",
+	}
+}
+
+impl std::fmt::Display for Schema {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		match self {
+			Schema::Unit => write!(f, "nil"),
+			Schema::Boolean => write!(f, "boolean"),
+			Schema::Integer => write!(f, "integer"),
+			Schema::Number => write!(f, "number"),
+			Schema::String => write!(f, "string"),
+			Schema::List(schema) => write!(f, "list of {schema}"),
+			Schema::Record(fields) => {
+				write!(f, "record {{ ")?;
+				for (i, (field, schema)) in fields.iter().enumerate() {
+					if i > 0 {
+						write!(f, ", ")?;
+					}
+					write!(f, "{field}: {schema}")?;
+				}
+				write!(f, " }}")
+			}
+		}
+	}
+}
+
+impl mlua::FromLua for Schema {
+	fn from_lua(value: mlua::Value, _lua: &mlua::Lua) -> mlua::Result<Self> {
+		match value {
+			mlua::Value::String(name) => match name.to_str()?.as_ref() {
+				"unit" => Ok(Schema::Unit),
+				"boolean" => Ok(Schema::Boolean),
+				"integer" => Ok(Schema::Integer),
+				"number" => Ok(Schema::Number),
+				"string" => Ok(Schema::String),
+				name => Err(mlua::Error::runtime(format!(
+					"unknown schema type \"{name}\""
+				))),
+			},
+			mlua::Value::Table(table) => {
+				if let Some(schema) = table.get::<Option<Schema>>("list")? {
+					Ok(Schema::List(Box::new(schema)))
+				} else if let Some(fields) = table.get::<Option<mlua::Table>>("record")? {
+					let mut fields = fields
+						.pairs::<Box<str>, Schema>()
+						.collect::<mlua::Result<Vec<_>>>()?;
+					// Keep error messages and display stable regardless of table order.
+					fields.sort_by(|(a, _), (b, _)| a.cmp(b));
+					Ok(Schema::Record(fields.into()))
+				} else {
+					Err(mlua::Error::runtime(
+						"schema tables must contain either a \"list\" or \"record\" field",
+					))
+				}
+			}
+			_ => Err(mlua::Error::FromLuaConversionError {
+				from: value.type_name(),
+				to: "Schema".into(),
+				message: None,
+			}),
+		}
+	}
+}
+
+/// Collects one of the hooks of every component attached to a piece.
+///
+/// Hooks should be collected before any are called, since they may attach or detach components.
+pub fn hooks<'resources>(
+	resources: &'resources resource::Manager,
+	piece: &character::Ref,
+	hook: impl Fn(&Component) -> Option<&mlua::Function>,
+) -> resource::Result<Vec<(&'resources str, mlua::Function)>> {
+	piece
+		.borrow()
+		.components
+		.keys()
+		.filter_map(|component_id| {
+			resources
+				.component
+				.get_key_value(component_id)
+				.map(|(id, component)| hook(component).map(|x| (id, x.clone())))
+				.transpose()
+		})
+		.collect::<resource::Result<Vec<_>>>()
+		.map(|mut hooks| {
+			// Components are stored in a hash map; sort them so that replays see the same order.
+			hooks.sort_by_key(|(id, _)| *id);
+			hooks
+		})
+}
+
+/// Calls one of the hooks of every component attached to a piece.
+pub fn dispatch(
+	resources: &resource::Manager,
+	piece: &character::Ref,
+	name: &str,
+	hook: impl Fn(&Component) -> Option<&mlua::Function>,
+	args: impl mlua::IntoLuaMulti + Clone,
+) -> anyhow::Result<()> {
+	let hooks = hooks(resources, piece, hook).context("failed to retrieve components")?;
+	for (component_id, hook) in hooks {
+		hook.call::<()>(args.clone())
+			.with_context(|| format!("failed to call {name} for component {component_id}"))?;
+	}
+	Ok(())
+}
@@ -4,16 +4,25 @@ use paste::paste;
 #[derive(Clone, Debug, rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
 pub enum MessagePrinter {
 	Console(Color),
-	Dialogue { speaker: Box<str>, progress: f64 },
+	Dialogue {
+		speaker: Box<str>,
+		progress: f64,
+	},
 	Combat(combat::Log),
+	/// Sent by a player, rather than printed by the game.
+	Chat {
+		username: Box<str>,
+	},
 }
 
-#[derive(Clone, Debug, rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
+#[derive(Clone, Debug, mlua::FromLua, rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
 pub struct Message {
 	pub text: Box<str>,
 	pub printer: MessagePrinter,
 }
 
+impl mlua::UserData for Message {}
+
 macro_rules! console_colored_print {
 	(normal) => {
 		fn print(&self, text: impl Into<Box<str>>) {
@@ -77,6 +86,10 @@ macro_rules! impl_console {
 				self.send_message(Message { text: text.into(), printer: MessagePrinter::Combat(log) })
 			}
 
+			fn chat(&self, username: impl Into<Box<str>>, text: impl Into<Box<str>>) {
+				self.send_message(Message { text: text.into(), printer: MessagePrinter::Chat { username: username.into() } })
+			}
+
 			$(console_colored_print! { $impl_colors } )*
 		}
 
@@ -95,6 +108,10 @@ macro_rules! impl_console {
 					this.0.combat_log(text, log);
 					Ok(())
 				});
+				methods.add_method("send_message", |_, this, message: Message| {
+					this.0.send_message(message);
+					Ok(())
+				});
 
 			}
 		}
@@ -111,3 +128,12 @@ impl_console! {
 	impl special: (0, 255, 0, 255),
 	let combat: (255, 255, 128, 255),
 }
+
+/// Forwards messages to a `LuaHandle`, for engine code that only has access to `runtime.console`.
+impl Handle for mlua::AnyUserData {
+	fn send_message(&self, message: Message) {
+		if let Err(msg) = mlua::ObjectLike::call_method::<()>(self, "send_message", message) {
+			error!("failed to send message: {msg}");
+		}
+	}
+}
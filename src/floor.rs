@@ -1,4 +1,5 @@
-use std::collections::HashMap;
+use crate::prelude::*;
+use std::collections::{HashMap, HashSet};
 
 #[derive(
 	PartialEq,
@@ -23,6 +24,16 @@ pub enum Tile {
 	Exit,
 }
 
+impl Tile {
+	/// Whether pieces are able to stand on this tile.
+	pub fn walkable(self) -> bool {
+		match self {
+			Tile::Floor | Tile::Exit => true,
+			Tile::Wall => false,
+		}
+	}
+}
+
 impl mlua::UserData for Tile {
 	fn add_methods<M: mlua::UserDataMethods<Self>>(methods: &mut M) {
 		methods.add_method("floor", |_, this, ()| Ok(matches!(this, Tile::Floor)));
@@ -88,6 +99,42 @@ impl Floor {
 			+ (y - chunk_id.1 * CHUNK_SIZE as i32) * CHUNK_SIZE as i32) as usize]
 	}
 
+	/// Every walkable tile which can be reached by walking from the given position.
+	pub fn reachable(&self, x: i32, y: i32) -> HashSet<(i32, i32)> {
+		let mut reachable = HashSet::new();
+		let mut frontier = vec![(x, y)];
+		while let Some((x, y)) = frontier.pop() {
+			if self.get(x, y).is_some_and(Tile::walkable) && reachable.insert((x, y)) {
+				frontier.extend(OrdDir::all().map(|direction| {
+					let (xoff, yoff) = direction.as_offset();
+					(x + xoff, y + yoff)
+				}));
+			}
+		}
+		reachable
+	}
+
+	/// Walls off any void that borders a walkable tile.
+	///
+	/// Returns the number of walls that were placed.
+	pub fn seal(&mut self) -> usize {
+		let openings = self
+			.iter()
+			.filter(|(_, _, tile)| tile.walkable())
+			.flat_map(|(x, y, _)| {
+				OrdDir::all().map(move |direction| {
+					let (xoff, yoff) = direction.as_offset();
+					(x + xoff, y + yoff)
+				})
+			})
+			.filter(|(x, y)| self.get(*x, *y).is_none())
+			.collect::<HashSet<_>>();
+		for (x, y) in &openings {
+			*self.get_mut(*x, *y) = Some(Tile::Wall);
+		}
+		openings.len()
+	}
+
 	/// This is not ordered!
 	pub fn iter(&self) -> impl Iterator<Item = (i32, i32, Tile)> + '_ {
 		self.chunks
@@ -20,6 +20,7 @@ pub mod floor;
 pub mod item;
 pub mod lua;
 pub mod nouns;
+pub mod replay;
 pub mod resource;
 pub mod value;
 pub mod vault;
@@ -33,6 +34,8 @@ pub mod world;
 pub use anyhow;
 
 pub use value::Value;
+/// Version of the engine, which must match between servers and clients.
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
 /// Arbitrary Unit of Time.
 pub type Aut = u32;
 /// The length of a "turn".
@@ -46,6 +46,7 @@ macro_rules! make_lua_enum{
 }
 
 make_lua_enum! { nouns::Pronouns: female, male, neutral, | object }
+make_lua_enum! { combat::DamageKind: physical, magical, | pure }
 
 impl mlua::FromLua for Nouns {
 	fn from_lua(value: mlua::Value, _: &Lua) -> Result<Self> {
@@ -63,6 +64,24 @@ impl mlua::FromLua for Nouns {
 	}
 }
 
+impl mlua::FromLua for combat::Attack {
+	fn from_lua(value: mlua::Value, _: &Lua) -> Result<Self> {
+		let Some(table) = value.as_table() else {
+			return Err(Error::runtime(format!(
+				"expected table, got {}",
+				value.type_name()
+			)));
+		};
+		Ok(combat::Attack {
+			magnitude: combat::clamp_amount(table.get("magnitude")?),
+			kind: table
+				.get::<Option<_>>("kind")?
+				.unwrap_or(combat::DamageKind::Physical),
+			pierce: table.get::<Option<_>>("pierce")?.unwrap_or(0),
+		})
+	}
+}
+
 impl mlua::UserData for Nouns {
 	fn add_fields<F: mlua::UserDataFields<Self>>(fields: &mut F) {
 		fields.add_field_method_get("name", |_, this| Ok(this.name.clone()));
@@ -83,11 +102,13 @@ pub fn init() -> Result<Lua> {
 			lua.create_function(|_, (action, heuristics)| Ok(Consider { action, heuristics }))
 		})?,
 	)?;
+	lua.load_from_function::<mlua::Value>("engine.types.duration", lua.create_function(duration)?)?;
 	lua.load_from_function::<mlua::Value>(
 		"engine.types.heuristic",
 		lua.create_function(heuristic)?,
 	)?;
 	lua.load_from_function::<mlua::Value>("engine.types.log", lua.create_function(log)?)?;
+	lua.load_from_function::<mlua::Value>("engine.types.piece", lua.create_function(piece)?)?;
 	lua.load_from_function::<mlua::Value>("engine.types.stats", lua.create_function(stats)?)?;
 	Ok(lua)
 }
@@ -106,13 +127,35 @@ fn combat(lua: &Lua, _: ()) -> Result<mlua::Table> {
 		)?,
 	)?;
 	combat.set(
-		"apply_pierce",
-		lua.create_function(|_, (pierce, magnitude)| {
-			if magnitude > 0 && magnitude <= pierce {
-				Ok((0, true))
-			} else {
-				Ok((magnitude, false))
-			}
+		"damage",
+		lua.create_function(
+			|lua,
+			 (user, target, attack, describe): (
+				character::Ref,
+				character::Ref,
+				combat::Attack,
+				mlua::Function,
+			)| {
+				let resources: resource::Handle =
+					lua.load(chunk!(require "runtime.resources")).eval()?;
+				let console: mlua::AnyUserData =
+					lua.load(chunk!(require "runtime.console")).eval()?;
+				let log = combat::damage(lua, &resources, console, &user, &target, attack, |log| {
+					Ok(describe.call::<Box<str>>(log.clone())?)
+				})
+				.map_err(Error::external)?;
+				let damage = match log {
+					combat::Log::Hit { damage } => damage,
+					_ => 0,
+				};
+				Ok((damage, log))
+			},
+		)?,
+	)?;
+	combat.set(
+		"heal",
+		lua.create_function(|lua, (target, amount): (character::Ref, u32)| {
+			combat::heal(lua, &target, amount)
 		})?,
 	)?;
 	Ok(combat)
@@ -160,6 +203,21 @@ fn action(lua: &Lua, _: ()) -> Result<mlua::Table> {
 	Ok(action)
 }
 
+fn duration(lua: &Lua, _: ()) -> Result<mlua::Table> {
+	use component::Duration;
+
+	let duration = lua.create_table()?;
+	duration.set("turn", Duration::Aut(TURN))?;
+	duration.set("rest", Duration::Rest)?;
+	duration.set("forever", Duration::Forever)?;
+	duration.set("aut", F::wrap(|aut| Ok(Duration::Aut(aut))))?;
+	duration.set(
+		"turns",
+		F::wrap(|turns: Aut| Ok(Duration::Aut(turns.saturating_mul(TURN)))),
+	)?;
+	Ok(duration)
+}
+
 fn heuristic(lua: &Lua, _: ()) -> Result<mlua::Table> {
 	fn saturating_cast(x: mlua::Integer) -> u32 {
 		x.max(u32::MIN as mlua::Integer)
@@ -198,6 +256,20 @@ fn log(lua: &Lua, _: ()) -> Result<mlua::Table> {
 	Ok(log)
 }
 
+fn piece(lua: &Lua, _: ()) -> Result<mlua::Table> {
+	let piece = lua.create_table()?;
+	piece.set(
+		"new",
+		lua.create_function(|lua, sheet: Box<str>| {
+			let resources: resource::Handle =
+				lua.load(chunk!(require "runtime.resources")).eval()?;
+			let sheet = resources.sheet.get(&sheet).map_err(Error::external)?;
+			character::Ref::from_sheet(&resources, sheet)
+		})?,
+	)?;
+	Ok(piece)
+}
+
 fn stats(lua: &Lua, _: ()) -> Result<mlua::Table> {
 	use character::Stats;
 
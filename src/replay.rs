@@ -0,0 +1,284 @@
+//! Recording and playback of runs.
+//!
+//! Every change to the world flows through [`world::Manager::perform_action`],
+//! so a run can be reproduced from the parameters used to start it
+//! and the list of actions it performed.
+//! Lua's random number generator is reseeded by the world before each action,
+//! starting from a run's [`Setup`], to keep scripts deterministic.
+//!
+//! Replays record automatic actions (those chosen by `world::Manager::tick`) alongside
+//! actions provided by players.
+//! Only the latter are fed back in during playback;
+//! the rest are compared against what the world does on its own, which is how desyncs are detected.
+
+use crate::prelude::*;
+use std::path::{Path, PathBuf};
+use std::{fs, io};
+
+/// Incremented whenever the replay format changes in an incompatible way.
+pub const VERSION: u32 = 1;
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+	#[error("replay uses format version {0}, but only version {VERSION} is supported")]
+	Version(u32),
+	#[error("desync on turn {turn}: expected {expected:?}, found {found:?}")]
+	Desync {
+		turn: usize,
+		expected: Option<Box<Entry>>,
+		found: Option<Box<Entry>>,
+	},
+	#[error(transparent)]
+	World(#[from] anyhow::Error),
+}
+
+#[derive(Clone, Debug, rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
+pub struct Replay {
+	/// Always [`VERSION`] for newly created replays.
+	pub version: u32,
+	pub setup: Setup,
+	/// Modules which were loaded when the replay was recorded.
+	///
+	/// Playing a replay with different modules will likely cause a desync,
+	/// but isn't forbidden.
+	pub modules: Vec<Module>,
+	pub entries: Vec<Entry>,
+}
+
+impl Replay {
+	pub fn new(setup: Setup, modules: Vec<Module>, entries: Vec<Entry>) -> Self {
+		Self {
+			version: VERSION,
+			setup,
+			modules,
+			entries,
+		}
+	}
+
+	/// Returns every module whose version differs from (or is missing in) `modules`.
+	pub fn mismatched_modules<'a>(
+		&'a self,
+		modules: &'a [Module],
+	) -> impl Iterator<Item = &'a Module> + 'a {
+		self.modules.iter().filter(|x| !modules.contains(x))
+	}
+}
+
+/// Everything needed to recreate a run before its first action.
+#[derive(Clone, Debug, rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
+pub struct Setup {
+	pub party: Vec<world::PartyReferenceBase>,
+	pub vault_set: Box<str>,
+	pub floor_seed: Box<str>,
+	/// The world's initial [`world::Manager::random_seed`].
+	pub random_seed: u32,
+}
+
+impl Setup {
+	/// Creates a world with recording enabled, seeding lua's random number generator from it.
+	pub fn start(
+		&self,
+		resources: &resource::Manager,
+		lua: &mlua::Lua,
+	) -> anyhow::Result<world::Manager> {
+		let mut world = world::Manager::new(self.party.iter().cloned(), resources)?;
+		world.random_seed = self.random_seed;
+		world.vault_set = self.vault_set.clone();
+		world.floor_seed = self.floor_seed.clone();
+		world.reseed(lua)?;
+		world.generate_floor(
+			&self.floor_seed,
+			resources.vault_set.get(&self.vault_set)?,
+			resources,
+		)?;
+		world.recording = Some(Vec::new());
+		Ok(world)
+	}
+}
+
+/// A resource module, identified by its name and a hash of its contents.
+#[derive(Clone, Debug, PartialEq, Eq, rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
+pub struct Module {
+	pub name: Box<str>,
+	pub version: u64,
+}
+
+impl Module {
+	/// Hashes every file within a module's directory.
+	///
+	/// The hash is stable across platforms and compiler versions,
+	/// so that modules may be compared between separate builds of the client and server.
+	///
+	/// # Errors
+	///
+	/// Fails if the directory or any of its files could not be read.
+	pub fn open(path: &Path) -> io::Result<Self> {
+		use blake2::digest::consts::U8;
+		use blake2::Digest;
+
+		let mut hasher = blake2::Blake2b::<U8>::new();
+		for relative in Self::files(path)? {
+			let name = Self::portable_path(&relative);
+			let contents = fs::read(path.join(&relative))?;
+			// Length prefixes keep the boundaries between names and contents unambiguous.
+			hasher.update((name.len() as u64).to_le_bytes());
+			hasher.update(name.as_bytes());
+			hasher.update((contents.len() as u64).to_le_bytes());
+			hasher.update(&contents);
+		}
+		Ok(Self {
+			name: path
+				.file_name()
+				.map(|x| x.to_string_lossy().into())
+				.unwrap_or_default(),
+			version: u64::from_le_bytes(hasher.finalize().into()),
+		})
+	}
+
+	/// Joins a path's components with '/', regardless of the platform's own separator.
+	///
+	/// Module files are hashed and sent under these paths, so that they match across platforms.
+	pub fn portable_path(path: &Path) -> String {
+		path.components()
+			.map(|x| x.as_os_str().to_string_lossy())
+			.collect::<Vec<_>>()
+			.join("/")
+	}
+
+	/// Lists every file within a module's directory, relative to it.
+	///
+	/// # Errors
+	///
+	/// Fails if the directory or any of its subdirectories could not be read.
+	pub fn files(path: &Path) -> io::Result<Vec<PathBuf>> {
+		fn list_directory(root: &Path, path: &Path, files: &mut Vec<PathBuf>) -> io::Result<()> {
+			let mut entries = fs::read_dir(path)?
+				.map(|x| x.map(|x| x.path()))
+				.collect::<io::Result<Vec<_>>>()?;
+			// Directory order is arbitrary, but the hash must not be.
+			entries.sort();
+			for entry in entries {
+				if entry.is_dir() {
+					list_directory(root, &entry, files)?;
+				} else {
+					files.push(entry.strip_prefix(root).unwrap_or(&entry).to_path_buf());
+				}
+			}
+			Ok(())
+		}
+
+		let mut files = Vec::new();
+		list_directory(path, path, &mut files)?;
+		Ok(files)
+	}
+}
+
+/// A single call to [`world::Manager::perform_action`].
+#[derive(Clone, Debug, PartialEq, rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
+pub struct Entry {
+	/// Sheet of the piece which acted.
+	pub sheet: Box<str>,
+	/// Position of the piece before it acted.
+	pub x: i32,
+	pub y: i32,
+	/// Whether the action was provided by a player rather than chosen by the world.
+	pub controlled: bool,
+	pub action: character::Action,
+}
+
+/// Re-runs a replay one player action at a time.
+pub struct Player<'replay> {
+	replay: &'replay Replay,
+	world: world::Manager,
+	/// How many entries have been checked against the world's own recording.
+	verified: usize,
+}
+
+impl<'replay> Player<'replay> {
+	/// # Errors
+	///
+	/// Fails if the replay's format is unsupported or its world could not be created.
+	pub fn new(
+		replay: &'replay Replay,
+		resources: &resource::Manager,
+		lua: &mlua::Lua,
+	) -> Result<Self, Error> {
+		if replay.version != VERSION {
+			return Err(Error::Version(replay.version));
+		}
+		Ok(Self {
+			replay,
+			world: replay.setup.start(resources, lua)?,
+			verified: 0,
+		})
+	}
+
+	pub fn world(&self) -> &world::Manager {
+		&self.world
+	}
+
+	/// Performs the next player action, along with any automatic actions preceding it.
+	///
+	/// Returns `false` once the replay has no actions left.
+	///
+	/// # Errors
+	///
+	/// Fails if the world diverges from the replay, or if an action fails.
+	pub fn step(
+		&mut self,
+		resources: &resource::Manager,
+		lua: &mlua::Lua,
+		console: impl console::Handle,
+	) -> Result<bool, Error> {
+		while self.world.tick(resources, lua, &console)? {}
+		self.verify()?;
+
+		let Some(entry) = self.replay.entries.get(self.verified) else {
+			return Ok(false);
+		};
+		if !entry.controlled {
+			// The replay expected the world to keep going, but it's waiting on a player.
+			return Err(self.desync(self.verified));
+		}
+		self.world
+			.perform_action(&console, resources, lua, entry.action.clone())?;
+		self.verify()?;
+		Ok(true)
+	}
+
+	/// Plays the rest of the replay, returning the resulting world.
+	///
+	/// # Errors
+	///
+	/// See [`Player::step`].
+	pub fn finish(
+		mut self,
+		resources: &resource::Manager,
+		lua: &mlua::Lua,
+		console: impl console::Handle,
+	) -> Result<world::Manager, Error> {
+		while self.step(resources, lua, &console)? {}
+		Ok(self.world)
+	}
+
+	fn verify(&mut self) -> Result<(), Error> {
+		let recording = self.world.recording.as_deref().unwrap_or_default();
+		// The world may keep acting on its own after the recording ended, which is fine.
+		while self.verified < recording.len().min(self.replay.entries.len()) {
+			if self.replay.entries.get(self.verified) != recording.get(self.verified) {
+				return Err(self.desync(self.verified));
+			}
+			self.verified += 1;
+		}
+		Ok(())
+	}
+
+	fn desync(&self, turn: usize) -> Error {
+		let recording = self.world.recording.as_deref().unwrap_or_default();
+		Error::Desync {
+			turn,
+			expected: self.replay.entries.get(turn).cloned().map(Box::new),
+			found: recording.get(turn).cloned().map(Box::new),
+		}
+	}
+}
@@ -75,6 +75,7 @@ pub struct Manager {
 	pub component: Resource<Rc<component::Component>>,
 	pub sheet: Resource<Rc<character::Sheet>>,
 	pub vault: Resource<Rc<vault::Vault>>,
+	pub vault_set: Resource<Rc<vault::Set>>,
 }
 
 #[derive(Debug, Clone, FromLua)]
@@ -131,11 +132,48 @@ fn sheet(id: &str, table: mlua::Table) -> anyhow::Result<character::Sheet> {
 		})
 	};
 
+	// Growth only lists the stats which change.
+	let growth = |table: mlua::Table| -> anyhow::Result<_> {
+		let stats = table
+			.get::<Option<mlua::Table>>("stats")?
+			.map(|table| -> anyhow::Result<_> {
+				Ok(character::Stats {
+					heart: table.get::<Option<_>>("heart")?.unwrap_or_default(),
+					soul: table.get::<Option<_>>("soul")?.unwrap_or_default(),
+					power: table.get::<Option<_>>("power")?.unwrap_or_default(),
+					defense: table.get::<Option<_>>("defense")?.unwrap_or_default(),
+					magic: table.get::<Option<_>>("magic")?.unwrap_or_default(),
+					resistance: table.get::<Option<_>>("resistance")?.unwrap_or_default(),
+				})
+			})
+			.transpose()?
+			.unwrap_or_default();
+		Ok(character::Growth {
+			experience: get!(table.experience)?,
+			stats,
+			abilities: table.get::<Option<_>>("abilities")?.unwrap_or_default(),
+		})
+	};
+
 	Ok(character::Sheet {
 		id: id.into(),
 		nouns: get!(table.nouns)?,
 		stats: stats(get!(table.stats)?)?,
 		abilities: table.get::<Option<_>>("abilities")?.unwrap_or_default(),
+		growth: table
+			.get::<Option<mlua::Table>>("growth")?
+			.map(|table| {
+				table
+					.sequence_values()
+					.map(|x| growth(x?))
+					.collect::<anyhow::Result<Vec<_>>>()
+			})
+			.transpose()?
+			.unwrap_or_default(),
+		components: table.get::<Option<_>>("components")?.unwrap_or_default(),
+		prototype: get!(table.prototype)?,
+		experience: table.get::<Option<_>>("experience")?.unwrap_or_default(),
+		corpse: table.get::<Option<_>>("corpse")?.unwrap_or(true),
 		on_consider: get!(table.on_consider)?,
 	})
 }
@@ -157,9 +195,21 @@ fn component(_id: &str, table: mlua::Table) -> anyhow::Result<component::Compone
 		name: get!(table.name)?,
 		icon: get!(table.icon)?,
 		visible: table.get::<Option<bool>>("visible")?.unwrap_or_default(),
+		schema: get!(table.schema)?,
+		duration: get!(table.duration)?,
 		on_attach: get!(table.on_attach)?,
 		on_detach: get!(table.on_detach)?,
+		on_expire: get!(table.on_expire)?,
 		on_turn: get!(table.on_turn)?,
+		on_rest: get!(table.on_rest)?,
+		on_attack: get!(table.on_attack)?,
+		on_defend: get!(table.on_defend)?,
+		on_damage: get!(table.on_damage)?,
+		on_deal_damage: get!(table.on_deal_damage)?,
+		on_death: get!(table.on_death)?,
+		on_move: get!(table.on_move)?,
+		on_floor_change: get!(table.on_floor_change)?,
+		on_buff: get!(table.on_buff)?,
 		on_debuff: get!(table.on_debuff)?,
 	})
 }
@@ -169,6 +219,11 @@ fn vault(_id: &str, table: mlua::Table) -> anyhow::Result<vault::Vault> {
 	let source = source.to_str()?;
 	let source = source.as_ref();
 	table.set(1, mlua::Nil)?;
+	// Remove flags before the remaining keys are interpreted as symbols.
+	let rotate = table.get::<Option<bool>>("rotate")?.unwrap_or(true);
+	let mirror = table.get::<Option<bool>>("mirror")?.unwrap_or(true);
+	table.set("rotate", mlua::Nil)?;
+	table.set("mirror", mlua::Nil)?;
 	let symbols: Box<[(char, vault::SymbolMeaning)]> = table
 		.pairs::<mlua::String, mlua::Either<vault::SymbolMeaning, floor::Tile>>()
 		.map(|x| {
@@ -187,7 +242,56 @@ fn vault(_id: &str, table: mlua::Table) -> anyhow::Result<vault::Vault> {
 			})
 		})
 		.collect::<mlua::Result<Box<[(char, vault::SymbolMeaning)]>>>()?;
-	Ok(vault::Vault::parse(source, symbols.iter())?)
+	Ok(vault::Vault {
+		rotate,
+		mirror,
+		..vault::Vault::parse(source, symbols.iter())?
+	})
+}
+
+fn vault_set(_id: &str, table: mlua::Table) -> anyhow::Result<vault::Set> {
+	let entry = |entry: mlua::Either<Box<str>, mlua::Table>| -> anyhow::Result<_> {
+		let table = match entry {
+			mlua::Either::Left(vault) => {
+				return Ok(vault::Entry {
+					vault,
+					weight: 1,
+					min: 0,
+					max: None,
+					depth: 0..=usize::MAX,
+				});
+			}
+			mlua::Either::Right(table) => table,
+		};
+		let depth = table
+			.get::<Option<mlua::Table>>("depth")?
+			.map(|depth| -> anyhow::Result<_> {
+				Ok(depth.get::<Option<usize>>(1)?.unwrap_or(0)
+					..=depth.get::<Option<usize>>(2)?.unwrap_or(usize::MAX))
+			})
+			.transpose()
+			.context("failed to retrieve depth")?
+			.unwrap_or(0..=usize::MAX);
+		Ok(vault::Entry {
+			vault: table.get(1).context("failed to retrieve vault")?,
+			weight: table.get::<Option<u32>>("weight")?.unwrap_or(1),
+			min: table.get::<Option<u32>>("min")?.unwrap_or(0),
+			max: table.get("max")?,
+			depth,
+		})
+	};
+
+	Ok(vault::Set {
+		vaults: get!(table.vaults).and_then(|vaults: mlua::Table| {
+			vaults
+				.sequence_values()
+				.map(|x| entry(x?))
+				.collect::<anyhow::Result<Vec<_>>>()
+		})?,
+		density: get!(table.density)?,
+		hall_ratio: table.get::<Option<f64>>("hall_ratio")?.unwrap_or(0.0),
+		final_depth: table.get("final_depth")?,
+	})
 }
 
 fn lib_searcher(
@@ -243,6 +347,7 @@ fn init<Load: FnMut(&str, &Path, &mut dyn FnMut() -> anyhow::Result<()>) -> anyh
 				("component", lua.create_table()?),
 				("sheet", lua.create_table()?),
 				("vault", lua.create_table()?),
+				("vault_set", lua.create_table()?),
 				(
 					"module",
 					lua.create_table_from([
@@ -311,7 +416,7 @@ fn produce(name: &str, prototypes: &mlua::Table) -> Result<Manager, Vec<anyhow::
 				$( produce!($type); )+
 			}
 		}
-	produce!(ability, sheet, component, vault);
+	produce!(ability, sheet, component, vault, vault_set);
 
 	products
 }
@@ -402,21 +507,61 @@ pub fn open<
 				.into(),
 			),
 		])),
-		component: Resource(HashMap::from_iter([(
-			":conscious".into(),
-			Component {
-				name: "Conscious".into(),
-				icon: None,
-				visible: false,
-				on_attach: None,
-				on_detach: None,
-				on_turn: None,
-				on_debuff: None,
-			}
-			.into(),
-		)])),
+		component: Resource(HashMap::from_iter([
+			(
+				":conscious".into(),
+				Component {
+					name: "Conscious".into(),
+					icon: None,
+					visible: false,
+					schema: Some(component::Schema::Unit),
+					duration: None,
+					on_attach: None,
+					on_detach: None,
+					on_expire: None,
+					on_turn: None,
+					on_rest: None,
+					on_attack: None,
+					on_defend: None,
+					on_damage: None,
+					on_deal_damage: None,
+					on_death: None,
+					on_move: None,
+					on_floor_change: None,
+					on_buff: None,
+					on_debuff: None,
+				}
+				.into(),
+			),
+			(
+				":downed".into(),
+				Component {
+					name: "Downed".into(),
+					icon: None,
+					visible: true,
+					schema: Some(component::Schema::Unit),
+					duration: None,
+					on_attach: None,
+					on_detach: None,
+					on_expire: None,
+					on_turn: None,
+					on_rest: None,
+					on_attack: None,
+					on_defend: None,
+					on_damage: None,
+					on_deal_damage: None,
+					on_death: None,
+					on_move: None,
+					on_floor_change: None,
+					on_buff: None,
+					on_debuff: None,
+				}
+				.into(),
+			),
+		])),
 		sheet: Resource(HashMap::new()),
 		vault: Resource(HashMap::new()),
+		vault_set: Resource(HashMap::new()),
 	};
 
 	let mut preliminary_modules = modules
@@ -474,7 +619,7 @@ pub fn open<
 							$( combine!($type); )+
 						}
 					}
-				combine!(ability, sheet, component, vault);
+				combine!(ability, sheet, component, vault, vault_set);
 				None
 			}
 			PreliminaryModule {
@@ -1,12 +1,95 @@
 use crate::floor::Tile;
-use crate::prelude::*;
+use std::collections::HashMap;
+use std::ops::RangeInclusive;
 
+/// A weighted collection of vaults which a floor can be generated from.
+#[derive(Clone, Debug)]
 pub struct Set {
-	pub vaults: Vec<Box<str>>,
+	pub vaults: Vec<Entry>,
 	/// Nodes per floor
 	pub density: u32,
 	/// ratio of halls to vaults.
-	pub hall_ratio: i32,
+	///
+	/// This is both the chance that a vault is attached to its neighbor by a straight hall,
+	/// and the number of winding corridors (per vault) that connect otherwise unused edges.
+	/// A ratio of 0.0 places vaults directly against each other.
+	pub hall_ratio: f64,
+	/// The deepest floor of a run using this set; reaching its exit ends the run in victory.
+	///
+	/// Without one, the party keeps descending until it falls.
+	pub final_depth: Option<usize>,
+}
+
+#[derive(Clone, Debug)]
+pub struct Entry {
+	/// Identifier of the vault resource this entry refers to.
+	pub vault: Box<str>,
+	/// How likely this vault is to be chosen relative to the rest of the set.
+	pub weight: u32,
+	/// How many times this vault must appear on a floor.
+	///
+	/// Vaults which have not reached their minimum are chosen before any others.
+	pub min: u32,
+	/// How many times this vault may appear on a floor.
+	pub max: Option<u32>,
+	/// Which floors this vault may appear on.
+	pub depth: RangeInclusive<usize>,
+}
+
+impl Entry {
+	fn allowed(&self, depth: usize, counts: &HashMap<Box<str>, u32>) -> bool {
+		self.depth.contains(&depth)
+			&& self
+				.max
+				.is_none_or(|max| counts.get(&self.vault).copied().unwrap_or(0) < max)
+	}
+
+	fn required(&self, depth: usize, counts: &HashMap<Box<str>, u32>) -> bool {
+		self.depth.contains(&depth) && counts.get(&self.vault).copied().unwrap_or(0) < self.min
+	}
+}
+
+impl Set {
+	/// Picks the next vault to place, given how many times each vault has been placed so far.
+	///
+	/// Required vaults are always chosen first.
+	pub fn choose(
+		&self,
+		depth: usize,
+		counts: &HashMap<Box<str>, u32>,
+		rng: &mut impl rand::Rng,
+	) -> Option<&Entry> {
+		use rand::seq::IndexedRandom;
+
+		let required = self
+			.vaults
+			.iter()
+			.filter(|entry| entry.required(depth, counts))
+			.collect::<Vec<_>>();
+		let candidates = if required.is_empty() {
+			self.vaults
+				.iter()
+				.filter(|entry| entry.allowed(depth, counts))
+				.collect::<Vec<_>>()
+		} else {
+			required
+		};
+		candidates
+			.choose_weighted(rng, |entry| entry.weight)
+			.ok()
+			.copied()
+	}
+
+	/// Returns every entry which has not been placed as many times as it requires.
+	pub fn missing<'a>(
+		&'a self,
+		depth: usize,
+		counts: &'a HashMap<Box<str>, u32>,
+	) -> impl Iterator<Item = &'a Entry> {
+		self.vaults
+			.iter()
+			.filter(move |entry| entry.required(depth, counts))
+	}
 }
 
 #[derive(Clone, Debug)]
@@ -16,13 +99,57 @@ pub struct Vault {
 
 	pub characters: Vec<(i32, i32, Box<str>)>,
 	pub edges: Vec<(i32, i32)>,
+	/// Where the party may enter the floor from.
+	pub entrances: Vec<(i32, i32)>,
+
+	/// Whether the vault may be rotated during placement.
+	pub rotate: bool,
+	/// Whether the vault may be mirrored during placement.
+	pub mirror: bool,
+}
+
+/// One of the eight orientations a vault can be placed in.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Transform {
+	/// Number of clockwise quarter turns, applied after mirroring.
+	pub rotation: u8,
+	/// Whether the vault is flipped horizontally.
+	pub mirrored: bool,
+}
+
+impl Transform {
+	pub fn all() -> impl Iterator<Item = Self> {
+		[false, true]
+			.into_iter()
+			.flat_map(|mirrored| (0..4).map(move |rotation| Transform { rotation, mirrored }))
+	}
+
+	/// Maps a position within a `width` by `height` vault to its transformed position.
+	pub fn apply(self, x: i32, y: i32, width: i32, height: i32) -> (i32, i32) {
+		let (mut x, mut y) = if self.mirrored {
+			(width - 1 - x, y)
+		} else {
+			(x, y)
+		};
+		let (mut width, mut height) = (width, height);
+		for _ in 0..self.rotation % 4 {
+			(x, y) = (height - 1 - y, x);
+			(width, height) = (height, width);
+		}
+		(x, y)
+	}
 }
 
 #[derive(Clone, Debug, mlua::FromLua)]
 pub enum SymbolMeaning {
 	Tile(Tile),
-	Character { sheet: Box<str>, tile: Tile },
+	Character {
+		sheet: Box<str>,
+		tile: Tile,
+	},
 	Edge,
+	/// A floor tile which the party may spawn on.
+	Entrance,
 	Void,
 }
 
@@ -54,6 +181,7 @@ impl Vault {
 		let mut tiles = Vec::new();
 		let mut characters = Vec::new();
 		let mut edges = Vec::new();
+		let mut entrances = Vec::new();
 
 		for (y, line) in lines.enumerate() {
 			for (x, c) in line.chars().enumerate() {
@@ -65,6 +193,7 @@ impl Vault {
 					// ...and for all unit SymbolMeaning variants.
 					' ' => Some(SymbolMeaning::Void),
 					'E' => Some(SymbolMeaning::Edge),
+					'@' => Some(SymbolMeaning::Entrance),
 					_ => None,
 				};
 				if let Some(action) = symbols
@@ -74,6 +203,7 @@ impl Vault {
 				{
 					match action {
 						SymbolMeaning::Edge => edges.push((x as i32, y as i32)),
+						SymbolMeaning::Entrance => entrances.push((x as i32, y as i32)),
 						SymbolMeaning::Character { sheet, tile: _ } => {
 							characters.push((x as i32, y as i32, sheet.clone()))
 						}
@@ -82,6 +212,7 @@ impl Vault {
 					tiles.push(match action {
 						SymbolMeaning::Edge | SymbolMeaning::Void => None,
 						SymbolMeaning::Tile(t) => Some(*t),
+						SymbolMeaning::Entrance => Some(Tile::Floor),
 						SymbolMeaning::Character { sheet: _, tile } => Some(*tile),
 					});
 				} else {
@@ -98,6 +229,55 @@ impl Vault {
 			width,
 			characters,
 			edges,
+			entrances,
+			rotate: true,
+			mirror: true,
 		})
 	}
+
+	/// Produces a copy of this vault with its tiles, characters, edges, and entrances transformed.
+	pub fn transform(&self, transform: Transform) -> Self {
+		let width = self.width as i32;
+		let height = self.height() as i32;
+		let (new_width, new_height) = if transform.rotation % 2 == 1 {
+			(height, width)
+		} else {
+			(width, height)
+		};
+		let apply = |x, y| transform.apply(x, y, width, height);
+
+		let mut tiles = vec![None; self.tiles.len()];
+		for (i, tile) in self.tiles.iter().enumerate() {
+			let (x, y) = apply(i as i32 % width, i as i32 / width);
+			tiles[(x + y * new_width) as usize] = *tile;
+		}
+		debug_assert_eq!(tiles.len(), (new_width * new_height) as usize);
+
+		Self {
+			tiles,
+			width: new_width as usize,
+			characters: self
+				.characters
+				.iter()
+				.map(|(x, y, sheet)| {
+					let (x, y) = apply(*x, *y);
+					(x, y, sheet.clone())
+				})
+				.collect(),
+			edges: self.edges.iter().map(|(x, y)| apply(*x, *y)).collect(),
+			entrances: self.entrances.iter().map(|(x, y)| apply(*x, *y)).collect(),
+			rotate: self.rotate,
+			mirror: self.mirror,
+		}
+	}
+
+	/// Every orientation this vault permits, including its original one.
+	pub fn variants(&self) -> Vec<Self> {
+		Transform::all()
+			.filter(|transform| {
+				(self.rotate || transform.rotation == 0) && (self.mirror || !transform.mirrored)
+			})
+			.map(|transform| self.transform(transform))
+			.collect()
+	}
 }
@@ -1,7 +1,8 @@
 use anyhow::Context;
 
 use crate::prelude::*;
-use std::{collections::VecDeque, rc::Rc};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::rc::Rc;
 
 /// This struct contains all information that is relevant during gameplay.
 #[derive(Debug, rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
@@ -16,6 +17,45 @@ pub struct Manager {
 	/// When exiting a dungeon, these sheets will be saved to a party struct.
 	pub party: Vec<PartyReference>,
 	pub inventory: Vec<String>,
+	/// How many pieces the party has defeated.
+	pub defeated: u32,
+	/// Set once the run is over, after which no more actions are performed.
+	pub outcome: Option<Outcome>,
+	/// The vault set each floor is generated from.
+	pub vault_set: Box<str>,
+	/// Combined with the depth of each floor to seed its generation.
+	pub floor_seed: Box<str>,
+	/// Passed to lua's `math.randomseed` before each action, then advanced.
+	///
+	/// Lua's generator can't be copied along with the world,
+	/// so this is what lets anyone holding a copy (such as a predicting client) reproduce its rolls.
+	pub random_seed: u32,
+	/// Every action performed so far, if the run is being recorded.
+	///
+	/// See [`replay::Setup::start`].
+	#[rkyv(with = rkyv::with::Skip)]
+	pub recording: Option<Vec<replay::Entry>>,
+}
+
+/// How a run came to an end.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
+pub enum Outcome {
+	/// A party member reached the exit of the final floor.
+	Victory,
+	/// Every party member was downed.
+	Defeat,
+}
+
+/// An overview of a run, shown to players once it has ended.
+#[derive(Clone, Debug, rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
+pub struct Summary {
+	pub outcome: Outcome,
+	/// The deepest floor the party reached.
+	pub floor: usize,
+	/// How many pieces the party defeated.
+	pub defeated: u32,
+	/// The name and experience of each party member.
+	pub party: Vec<(Box<str>, u32)>,
 }
 
 #[derive(Clone, Debug, rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
@@ -36,11 +76,36 @@ impl PartyReference {
 }
 
 // this is probably uneccessary and just makes main.rs look nicer
+#[derive(Clone, Debug, rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
 pub struct PartyReferenceBase {
 	pub sheet: Box<str>,
 	pub accent_color: Color,
 }
 
+/// Reasons a generated floor may be rejected.
+#[derive(Debug, thiserror::Error)]
+pub enum FloorError {
+	#[error("no vaults were placed")]
+	Empty,
+	#[error("missing required vault {0}")]
+	MissingVault(Box<str>),
+	#[error("floor has no exit")]
+	NoExit,
+	#[error("{0} tiles are unreachable")]
+	Unreachable(usize),
+}
+
+/// What floor generation produced, for validation.
+struct Layout {
+	/// Where the party spawns.
+	///
+	/// This is one of the placed vaults' entrances if there are any,
+	/// or a walkable tile within the first vault placed otherwise.
+	start: Option<(i32, i32)>,
+	/// How many times each vault was placed.
+	counts: HashMap<Box<str>, u32>,
+}
+
 #[derive(Clone, Debug, rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
 pub struct Location {
 	/// Which level is currently loaded.
@@ -52,7 +117,7 @@ impl Manager {
 	pub fn new(
 		party_blueprint: impl Iterator<Item = PartyReferenceBase>,
 		resources: &resource::Manager,
-	) -> resource::Result<Self> {
+	) -> anyhow::Result<Self> {
 		let mut party = Vec::new();
 		let mut characters = VecDeque::new();
 
@@ -62,11 +127,15 @@ impl Manager {
 		} in party_blueprint
 		{
 			let sheet = resources.sheet.get(&sheet)?;
-			let character = character::Ref::new(character::Piece::new((**sheet).clone()));
-			character.borrow_mut().components.insert(
-				"std:teams".into(),
-				Value::OrderedTable([Value::String(":players".into())].into()),
-			);
+			let character = character::Ref::from_sheet(resources, sheet)?;
+			{
+				let mut piece = character.borrow_mut();
+				piece.components.insert(
+					"std:teams".into(),
+					Value::OrderedTable([Value::String(":players".into())].into()),
+				);
+				piece.invalidate_stats();
+			}
 			if characters.is_empty() {
 				character
 					.borrow_mut()
@@ -87,6 +156,12 @@ impl Manager {
 			items: Vec::new(),
 
 			party,
+			defeated: 0,
+			outcome: None,
+			vault_set: Box::default(),
+			floor_seed: Box::default(),
+			random_seed: 0,
+			recording: None,
 			inventory: vec![
 				"items/aloe".into(),
 				"items/apple".into(),
@@ -113,6 +188,23 @@ impl Manager {
 		})
 	}
 
+	/// Describes how the run went, if it has ended.
+	pub fn summary(&self) -> Option<Summary> {
+		Some(Summary {
+			outcome: self.outcome?,
+			floor: self.location.floor,
+			defeated: self.defeated,
+			party: self
+				.party
+				.iter()
+				.map(|member| {
+					let piece = member.piece.borrow();
+					(piece.sheet.nouns.name.clone(), piece.experience)
+				})
+				.collect(),
+		})
+	}
+
 	pub fn next_character(&self) -> &character::Ref {
 		&self.characters[0]
 	}
@@ -124,25 +216,170 @@ impl Manager {
 		})
 	}
 
+	/// Replaces the current floor with one generated from the given vault set.
+	///
+	/// Floors which fail validation are regenerated using a seed derived from the original.
 	pub fn generate_floor(
 		&mut self,
 		seed: &str,
 		set: &vault::Set,
 		resources: &resource::Manager,
-	) -> resource::Result<()> {
-		use rand::seq::{IndexedRandom, SliceRandom};
-		use rand::SeedableRng;
-
-		const SEED_LENGTH: usize = 32;
+	) -> anyhow::Result<()> {
+		/// Failing this many times probably means the set can't satisfy its own requirements.
+		const MAX_ATTEMPTS: u32 = 16;
 
 		let _enter = tracing::error_span!("level gen", seed).entered();
-		let mut seed_slice = [0; SEED_LENGTH];
-		for (str_byte, seed_byte) in seed.bytes().take(SEED_LENGTH).zip(seed_slice.iter_mut()) {
-			*seed_byte = str_byte;
+		let party = self
+			.party
+			.iter()
+			.map(|member| member.piece.clone())
+			.collect::<Vec<_>>();
+		let mut attempt = 0;
+		loop {
+			let seed = if attempt == 0 {
+				seed.to_string()
+			} else {
+				format!("{seed}/{attempt}")
+			};
+			// Clear out anything left over from the previous floor (or attempt).
+			self.current_floor = Floor::default();
+			self.items.clear();
+			self.characters
+				.retain(|character| party.contains(character));
+
+			let layout = self.place_vaults(&seed, set, resources)?;
+			match self.validate_floor(&layout, set) {
+				Ok(()) => {
+					if let Some((x, y)) = layout.start {
+						self.place_party(&party, x, y);
+					}
+					for piece in &party {
+						component::dispatch(
+							resources,
+							piece,
+							"on_floor_change",
+							|c| c.on_floor_change.as_ref(),
+							(piece.clone(), self.location.floor),
+						)?;
+					}
+					return Ok(());
+				}
+				Err(msg) if attempt + 1 < MAX_ATTEMPTS => {
+					warn!(attempt, "rejected floor: {msg}");
+					attempt += 1;
+				}
+				Err(msg) => {
+					return Err(msg).with_context(|| {
+						format!("failed to generate a valid floor after {MAX_ATTEMPTS} attempts")
+					});
+				}
+			}
+		}
+	}
+
+	/// Checks that a generated floor is actually playable.
+	///
+	/// Any void bordering the floor is walled off before checking reachability.
+	fn validate_floor(&mut self, layout: &Layout, set: &vault::Set) -> Result<(), FloorError> {
+		if let Some(entry) = set.missing(self.location.floor, &layout.counts).next() {
+			return Err(FloorError::MissingVault(entry.vault.clone()));
+		}
+		let Some((x, y)) = layout.start else {
+			return Err(FloorError::Empty);
+		};
+		let sealed = self.current_floor.seal();
+		if sealed > 0 {
+			debug!(sealed, "sealed stray openings");
+		}
+		let reachable = self.current_floor.reachable(x, y);
+		let mut exits = 0;
+		let mut unreachable = 0;
+		for (x, y, tile) in self.current_floor.iter() {
+			if tile.walkable() && !reachable.contains(&(x, y)) {
+				unreachable += 1;
+			} else if tile == floor::Tile::Exit {
+				exits += 1;
+			}
+		}
+		if unreachable > 0 {
+			Err(FloorError::Unreachable(unreachable))
+		} else if exits == 0 {
+			Err(FloorError::NoExit)
+		} else {
+			Ok(())
+		}
+	}
+
+	/// Moves each of the given pieces onto the nearest unoccupied floor tile to (x, y).
+	///
+	/// Pieces which don't fit anywhere are left where they are.
+	fn place_party(&mut self, party: &[character::Ref], x: i32, y: i32) {
+		// Party pieces are still wherever the previous floor left them, so they can't block anything.
+		let mut occupied = self
+			.characters
+			.iter()
+			.filter(|character| !party.contains(character))
+			.map(|character| {
+				let character = character.borrow();
+				(character.x, character.y)
+			})
+			.collect::<HashSet<_>>();
+		let mut frontier = VecDeque::from([(x, y)]);
+		let mut visited = HashSet::from([(x, y)]);
+		let mut party = party.iter();
+		while let Some((x, y)) = frontier.pop_front() {
+			if self.current_floor.get(x, y) == Some(floor::Tile::Floor) && occupied.insert((x, y)) {
+				let Some(piece) = party.next() else {
+					return;
+				};
+				let mut piece = piece.borrow_mut();
+				piece.x = x;
+				piece.y = y;
+			}
+			for (xoff, yoff) in OrdDir::all().map(OrdDir::as_offset) {
+				let next = (x + xoff, y + yoff);
+				if self
+					.current_floor
+					.get(next.0, next.1)
+					.is_some_and(floor::Tile::walkable)
+					&& visited.insert(next)
+				{
+					frontier.push_back(next);
+				}
+			}
 		}
-		let mut rng = rand::rngs::StdRng::from_seed(seed_slice);
+		if party.next().is_some() {
+			warn!("not enough room to place the party");
+		}
+	}
+
+	fn place_vaults(
+		&mut self,
+		seed: &str,
+		set: &vault::Set,
+		resources: &resource::Manager,
+	) -> anyhow::Result<Layout> {
+		use rand::seq::{IndexedRandom, SliceRandom};
+		use rand::{Rng, SeedableRng};
+
+		use blake2::Digest;
+
+		// Hash the whole seed rather than truncating it,
+		// so that long seeds (and the attempts derived from them) still differ.
+		let mut rng = rand_chacha::ChaCha8Rng::from_seed(blake2::Blake2s256::digest(seed).into());
 
-		let mut edges = vec![(4, 4)];
+		let depth = self.location.floor;
+		// Edges are tagged with the index of the vault they belong to,
+		// so that corridors don't loop a vault back onto itself.
+		let mut edges = vec![(4, 4, usize::MAX)];
+		// How many times each vault has been placed on this floor.
+		let mut counts = HashMap::<Box<str>, u32>::new();
+		let mut placed = 0;
+		let mut start = None;
+		let mut entrances = Vec::new();
+		// Halls which nothing could be attached to.
+		// These aren't retried during placement, but corridors may still connect them.
+		let mut dead_ends = Vec::new();
 
 		'placement: for _ in 0..set.density {
 			// This loop allows for retries each time placement fails.
@@ -153,39 +390,247 @@ impl Manager {
 				// returning the remaining halves in reverse order.
 				let (placement_edge, _) = edges.partial_shuffle(&mut rng, 1);
 				// This slice should only ever be 0 or 1 elements.
-				let Some((px, py)) = placement_edge.first().copied() else {
+				let Some((px, py, owner)) = placement_edge.first().copied() else {
 					// If there are no remaining edges, we cannot place any more vaults.
 					break 'placement;
 				};
 				// Remove the placement edge we chose.
 				edges.pop();
-				let Some(vault) = set.vaults.choose(&mut rng) else {
-					warn!("set has no vaults");
+				let Some(entry) = set.choose(depth, &counts, &mut rng) else {
+					warn!("set has no placeable vaults");
 					break 'placement;
 				};
-				let vault = resources.vault.get(vault)?;
-				// for every possible edge of the vault (shuffled), check if it fits.
-				let mut potential_edges = vault.edges.clone();
-				potential_edges.shuffle(&mut rng);
-				for (i, (ex, ey)) in potential_edges.iter().enumerate() {
-					// adjust the placment position so that px, py and ex, ey overlap.
-					let x = px - ex;
-					let y = py - ey;
-					if self.try_apply_vault(x, y, vault, resources)? {
-						for (px, py) in potential_edges
-							.iter()
-							.take(i)
-							.chain(potential_edges.iter().skip(i + 1))
-						{
-							edges.push((x + px, y + py));
+				let vault = resources.vault.get(&entry.vault)?;
+				// The first vault has nothing to connect to, so it can't be given a hall or doorway.
+				let connected = !counts.is_empty();
+				let hall = if connected && rng.random_bool(set.hall_ratio.clamp(0.0, 1.0)) {
+					let length = rng.random_range(2..=6);
+					self.try_apply_hall(px, py, length)
+				} else {
+					None
+				};
+				let (px, py) = hall.unwrap_or((px, py));
+				// for every orientation of the vault (shuffled), try each of its edges.
+				let mut variants = vault.variants();
+				variants.shuffle(&mut rng);
+				for vault in &variants {
+					// for every possible edge of the vault (shuffled), check if it fits.
+					let mut potential_edges = vault.edges.clone();
+					potential_edges.shuffle(&mut rng);
+					for (i, (ex, ey)) in potential_edges.iter().enumerate() {
+						// adjust the placment position so that px, py and ex, ey overlap.
+						let x = px - ex;
+						let y = py - ey;
+						if self.try_apply_vault(x, y, vault, resources)? {
+							if connected {
+								self.apply_doorway(px, py);
+							} else {
+								start = vault
+									.tiles
+									.iter()
+									.position(|tile| tile.is_some_and(floor::Tile::walkable))
+									.map(|i| {
+										(x + (i % vault.width) as i32, y + (i / vault.width) as i32)
+									});
+							}
+							entrances
+								.extend(vault.entrances.iter().map(|(ex, ey)| (x + ex, y + ey)));
+							*counts.entry(entry.vault.clone()).or_default() += 1;
+							for (px, py) in potential_edges
+								.iter()
+								.take(i)
+								.chain(potential_edges.iter().skip(i + 1))
+							{
+								edges.push((x + px, y + py, placed));
+							}
+							placed += 1;
+							break 'edges;
 						}
-						break 'edges;
 					}
 				}
+				if let Some((hx, hy)) = hall {
+					dead_ends.push((hx, hy, owner));
+				}
 			}
 		}
+		edges.append(&mut dead_ends);
 
-		Ok(())
+		let corridors = (set.hall_ratio.max(0.0) * placed as f64).round() as usize;
+		self.apply_corridors(&mut edges, corridors, &mut rng);
+		// Any edges which weren't used would otherwise open into the void.
+		for (x, y, _) in edges {
+			let tile = self.current_floor.get_mut(x, y);
+			if tile.is_none() {
+				*tile = Some(floor::Tile::Wall);
+			}
+		}
+
+		if let Some(entrance) = entrances.choose(&mut rng) {
+			start = Some(*entrance);
+		}
+
+		Ok(Layout { start, counts })
+	}
+
+	/// Opens an edge shared by two vaults, walling off any void around it.
+	fn apply_doorway(&mut self, x: i32, y: i32) {
+		*self.current_floor.get_mut(x, y) = Some(floor::Tile::Floor);
+		self.apply_walls(x, y);
+	}
+
+	/// Surrounds a tile with walls wherever it borders the void.
+	fn apply_walls(&mut self, x: i32, y: i32) {
+		for (xoff, yoff) in OrdDir::all().map(OrdDir::as_offset) {
+			let tile = self.current_floor.get_mut(x + xoff, y + yoff);
+			if tile.is_none() {
+				*tile = Some(floor::Tile::Wall);
+			}
+		}
+	}
+
+	/// Connects up to `corridors` pairs of unused edges with winding corridors.
+	///
+	/// Each edge is paired with the nearest edge belonging to a different vault.
+	/// Edges which were connected are removed from `edges`.
+	fn apply_corridors(
+		&mut self,
+		edges: &mut Vec<(i32, i32, usize)>,
+		mut corridors: usize,
+		rng: &mut impl rand::Rng,
+	) {
+		use rand::seq::SliceRandom;
+
+		/// Corridors longer than this are more confusing than they are interesting.
+		const MAX_CORRIDOR_DISTANCE: u32 = 24;
+
+		edges.shuffle(rng);
+		let mut i = 0;
+		while corridors > 0 && i < edges.len() {
+			let (x, y, vault) = edges[i];
+			let nearest = edges
+				.iter()
+				.enumerate()
+				.filter(|(_, (_, _, other))| *other != vault)
+				.map(|(j, (ox, oy, _))| (j, (ox - x).unsigned_abs().max((oy - y).unsigned_abs())))
+				.filter(|(_, distance)| *distance <= MAX_CORRIDOR_DISTANCE)
+				.min_by_key(|(_, distance)| *distance);
+			if let Some((j, _)) = nearest {
+				let (ox, oy, _) = edges[j];
+				if self.try_apply_corridor((x, y), (ox, oy), rng.random()) {
+					// Remove the later index first so that the earlier one stays valid.
+					edges.swap_remove(i.max(j));
+					edges.swap_remove(i.min(j));
+					corridors -= 1;
+					continue;
+				}
+			}
+			i += 1;
+		}
+	}
+
+	/// Attempts to carve a corridor through the void between two edges.
+	///
+	/// Corridors never pass next to an existing floor, so they can't breach any rooms along the way.
+	/// `salt` perturbs the path's cost to make it wind.
+	fn try_apply_corridor(&mut self, from: (i32, i32), to: (i32, i32), salt: u32) -> bool {
+		use floor::Tile;
+
+		/// How far a corridor may stray outside of the rectangle between its ends.
+		const MARGIN: i32 = 6;
+
+		fn noise(x: i32, y: i32, salt: u32) -> u16 {
+			let mut hash =
+				(x as u32).wrapping_mul(0x9E37_79B1) ^ (y as u32).wrapping_mul(0x85EB_CA77) ^ salt;
+			hash ^= hash >> 15;
+			hash = hash.wrapping_mul(0x2C1B_3C6D);
+			hash ^= hash >> 12;
+			(hash % 4) as u16
+		}
+
+		let (min_x, max_x) = (from.0.min(to.0) - MARGIN, from.0.max(to.0) + MARGIN);
+		let (min_y, max_y) = (from.1.min(to.1) - MARGIN, from.1.max(to.1) + MARGIN);
+		let floor = &self.current_floor;
+		let open = |x, y| matches!(floor.get(x, y), Some(Tile::Floor | Tile::Exit));
+
+		let mut dijkstra = astar::Floor::target(&[to]);
+		dijkstra.explore(from.0, from.1, |x, y, base| {
+			let passable = (x, y) == from
+				|| (x, y) == to
+				|| (floor.get(x, y).is_none()
+					&& !OrdDir::all()
+						.map(OrdDir::as_offset)
+						.any(|(xoff, yoff)| open(x + xoff, y + yoff)));
+			if (min_x..=max_x).contains(&x) && (min_y..=max_y).contains(&y) && passable {
+				base + 1 + noise(x, y, salt)
+			} else {
+				astar::IMPASSABLE
+			}
+		});
+
+		let mut path = vec![from];
+		let (mut x, mut y) = from;
+		// Stepping should always approach the target, but the limit guards against a bad map.
+		let limit = ((max_x - min_x + 1) * (max_y - min_y + 1)) as usize;
+		while (x, y) != to {
+			let Some(direction) = dijkstra.step(x, y) else {
+				return false;
+			};
+			let (xoff, yoff) = direction.as_offset();
+			(x, y) = (x + xoff, y + yoff);
+			path.push((x, y));
+			if path.len() > limit {
+				return false;
+			}
+		}
+
+		for &(x, y) in &path {
+			*self.current_floor.get_mut(x, y) = Some(Tile::Floor);
+		}
+		for &(x, y) in &path {
+			self.apply_walls(x, y);
+		}
+		true
+	}
+
+	/// Extends a straight hall outward from an edge, returning the position of its far end.
+	///
+	/// Returns `None` (and leaves the floor untouched) if the hall would collide with anything.
+	fn try_apply_hall(&mut self, x: i32, y: i32, length: i32) -> Option<(i32, i32)> {
+		use floor::Tile;
+
+		// Halls point away from the tiles they're attached to.
+		let (dx, dy) = CardDir::all().map(CardDir::as_offset).find(|(dx, dy)| {
+			matches!(
+				self.current_floor.get(x - dx, y - dy),
+				Some(Tile::Floor | Tile::Exit)
+			) && self.current_floor.get(x + dx, y + dy).is_none()
+		})?;
+		let hall = (0..length).map(|i| (x + dx * i, y + dy * i));
+		// The far end needs to be free too, since it becomes the next vault's doorway.
+		for (i, (hx, hy)) in hall
+			.clone()
+			.chain([(x + dx * length, y + dy * length)])
+			.enumerate()
+		{
+			// The first tile of the hall is flanked by its vault's walls.
+			let sides = if i == 0 { 0..=0 } else { -1..=1 };
+			if sides
+				.map(|side| (hx + dy * side, hy + dx * side))
+				.any(|(x, y)| self.current_floor.get(x, y).is_some())
+			{
+				return None;
+			}
+		}
+		for (hx, hy) in hall {
+			*self.current_floor.get_mut(hx, hy) = Some(Tile::Floor);
+			for side in [-1, 1] {
+				let wall = self.current_floor.get_mut(hx + dy * side, hy + dx * side);
+				if wall.is_none() {
+					*wall = Some(Tile::Wall);
+				}
+			}
+		}
+		Some((x + dx * length, y + dy * length))
 	}
 
 	fn try_apply_vault(
@@ -194,7 +639,7 @@ impl Manager {
 		y: i32,
 		vault: &Vault,
 		resources: &resource::Manager,
-	) -> resource::Result<bool> {
+	) -> anyhow::Result<bool> {
 		for (row, y) in vault
 			.tiles
 			.chunks(vault.width)
@@ -220,12 +665,13 @@ impl Manager {
 		}
 
 		for (xoff, yoff, sheet) in &vault.characters {
-			let piece = character::Piece {
-				x: x + xoff,
-				y: y + yoff,
-				..character::Piece::new((**resources.sheet.get(sheet)?).clone())
-			};
-			self.characters.push_front(character::Ref::new(piece));
+			let piece = character::Ref::from_sheet(resources, resources.sheet.get(sheet)?)?;
+			{
+				let mut piece = piece.borrow_mut();
+				piece.x = x + xoff;
+				piece.y = y + yoff;
+			}
+			self.characters.push_front(piece);
 		}
 
 		Ok(true)
@@ -241,15 +687,34 @@ impl Manager {
 		lua: &mlua::Lua,
 		console: impl console::Handle,
 	) -> anyhow::Result<bool> {
-		let character = self.next_character();
+		if self.outcome.is_some() {
+			return Ok(false);
+		}
+		// Nobody is left to take a turn for, so the queue would never reach a conscious piece again.
+		let Some(leader) = self.standing_party().next() else {
+			return Ok(false);
+		};
+		// Someone who got back up after the whole party was downed needs to be given control again.
+		if !self
+			.standing_party()
+			.any(|piece| piece.borrow().components.contains_key(":conscious"))
+		{
+			leader
+				.borrow_mut()
+				.components
+				.insert(":conscious".into(), Value::Unit);
+		}
+		let character = self.next_character().clone();
 		if !character.borrow().components.contains_key(":conscious") {
-			let action = self
-				.consider_action(lua, character.clone())
-				.context("failed to consider action")?
-				.unwrap_or(character::Action::Ability(
-					":wait".into(),
-					Value::Integer(TURN as i64),
-				));
+			self.reseed(lua)?;
+			let wait = character::Action::Ability(":wait".into(), Value::Integer(TURN as i64));
+			let action = if character.borrow().components.contains_key(":downed") {
+				wait
+			} else {
+				self.consider_action(lua, character.clone())
+					.context("failed to consider action")?
+					.unwrap_or(wait)
+			};
 			self.perform_action(&console, resources, lua, action)
 				.context("failed to perform action")?;
 			Ok(true)
@@ -258,6 +723,18 @@ impl Manager {
 		}
 	}
 
+	/// Seeds lua's random number generator from [`Manager::random_seed`],
+	/// and advances it for the next action.
+	pub fn reseed(&mut self, lua: &mlua::Lua) -> mlua::Result<()> {
+		use rand::{Rng, SeedableRng};
+
+		let random_seed = self.random_seed;
+		lua.load(mlua::chunk!(math.randomseed($random_seed)))
+			.exec()?;
+		self.random_seed = rand_chacha::ChaCha8Rng::seed_from_u64(random_seed.into()).random();
+		Ok(())
+	}
+
 	pub fn consider_action(
 		&self,
 		lua: &mlua::Lua,
@@ -286,7 +763,22 @@ impl Manager {
 		lua: &mlua::Lua,
 		action: character::Action,
 	) -> anyhow::Result<()> {
+		if self.outcome.is_some() {
+			warn!("attempted to perform an action after the run ended");
+			return Ok(());
+		}
+		self.reseed(lua)?;
 		let next_character = self.next_character().clone();
+		if let Some(recording) = &mut self.recording {
+			let piece = next_character.borrow();
+			recording.push(replay::Entry {
+				sheet: piece.sheet.id.clone(),
+				x: piece.x,
+				y: piece.y,
+				controlled: piece.components.contains_key(":conscious"),
+				action: action.clone(),
+			});
+		}
 
 		let delay = next_character.borrow().action_delay;
 		// The delay represents how many auts must pass until this character's next action.
@@ -296,25 +788,32 @@ impl Manager {
 			let action_delay = &mut i.borrow_mut().action_delay;
 			*action_delay = action_delay.saturating_sub(delay);
 		}
+		// Timed components run down alongside action delays.
+		for i in &self.characters {
+			i.elapse(resources, delay)
+				.context("failed to expire components")?;
+		}
 		// Once an action has been provided, tell components that a turn has been taken.
-		// This has to be a copy because on_turn may mutate `next_character`.
-		let original_components = next_character
-			.borrow()
-			.components
-			.keys()
-			.filter_map(|component_id| {
-				resources
-					.component
-					.get_key_value(component_id)
-					.map(|(id, component)| component.on_turn.clone().map(|x| (id, x)))
-					.transpose()
-			})
-			.collect::<resource::Result<Vec<(&str, mlua::Function)>>>()
-			.context("failed to retrieve components")?;
-		for (component_id, on_turn) in original_components {
-			on_turn
-				.call::<()>((next_character.clone(), delay))
-				.with_context(|| format!("failed to call on_turn for component {component_id}"))?;
+		component::dispatch(
+			resources,
+			&next_character,
+			"on_turn",
+			|c| c.on_turn.as_ref(),
+			(next_character.clone(), delay),
+		)?;
+		if let character::Action::Ability(ability, _) = &action
+			&& &**ability == ":wait"
+		{
+			component::dispatch(
+				resources,
+				&next_character,
+				"on_rest",
+				|c| c.on_rest.as_ref(),
+				next_character.clone(),
+			)?;
+			next_character
+				.rest(resources)
+				.context("failed to expire components")?;
 		}
 
 		let delay = match action {
@@ -324,8 +823,9 @@ impl Manager {
 					(next_character.x, next_character.y)
 				};
 				// For distances of 1 tile, don't bother using a dijkstra map.
-				if let Some(direction) = OrdDir::from_offset(target_x - x, target_y - y) {
-					self.move_piece(&next_character, direction, console)
+				let delay = if let Some(direction) = OrdDir::from_offset(target_x - x, target_y - y)
+				{
+					self.move_piece(&next_character, direction, &console)
 				} else {
 					let mut dijkstra = astar::Floor::target(&[(target_x, target_y)]);
 					dijkstra.explore(x, y, |x, y, base| {
@@ -340,11 +840,21 @@ impl Manager {
 						}
 					});
 					if let Some(direction) = dijkstra.step(x, y) {
-						self.move_piece(&next_character, direction, console)
+						self.move_piece(&next_character, direction, &console)
 					} else {
 						None
 					}
+				};
+				if delay.is_some() {
+					component::dispatch(
+						resources,
+						&next_character,
+						"on_move",
+						|c| c.on_move.as_ref(),
+						(next_character.clone(), x, y),
+					)?;
 				}
+				delay
 			}
 			character::Action::Ability(ability, arguments) => self.act(
 				resources
@@ -352,18 +862,13 @@ impl Manager {
 					.get(&ability)
 					.context("failed to retrieve ability")?
 					.clone(),
-				next_character,
+				next_character.clone(),
 				lua,
 				arguments,
-				console,
+				&console,
 			)?,
 		};
 
-		// Remove dead characters.
-		// TODO: Does this belong here?
-		self.characters
-			.retain(|character| character.borrow().hp > 0);
-
 		let character = self
 			.characters
 			.pop_front()
@@ -383,9 +888,117 @@ impl Manager {
 				.unwrap_or(self.characters.len()),
 			character,
 		);
+
+		self.process_deaths(&console);
+		self.check_outcome(resources, &console)
+			.context("failed to check outcome")?;
+		Ok(())
+	}
+
+	/// Ends the run if the party has been wiped out, and takes it down a floor if it reached an exit.
+	///
+	/// Reaching an exit on the vault set's final depth ends the run in victory instead.
+	fn check_outcome(
+		&mut self,
+		resources: &resource::Manager,
+		console: impl console::Handle,
+	) -> anyhow::Result<()> {
+		if self.standing_party().next().is_none() {
+			console.print_defeat("The party has fallen.");
+			self.outcome = Some(Outcome::Defeat);
+		} else if self.standing_party().any(|piece| {
+			let piece = piece.borrow();
+			self.current_floor.get(piece.x, piece.y) == Some(floor::Tile::Exit)
+		}) {
+			let set = resources.vault_set.get(&self.vault_set)?;
+			if set
+				.final_depth
+				.is_some_and(|depth| self.location.floor >= depth)
+			{
+				console.print_special("The party escapes!");
+				self.outcome = Some(Outcome::Victory);
+			} else {
+				self.location.floor += 1;
+				let seed = format!("{}/floor {}", self.floor_seed, self.location.floor);
+				self.generate_floor(&seed, set, resources)?;
+				console.print_special("The party descends deeper into the dungeon.");
+			}
+		}
 		Ok(())
 	}
 
+	/// Deals with any pieces whose health has been depleted.
+	///
+	/// Party members are downed rather than removed, passing control on to a standing member if they had it.
+	/// Anything else is removed from the board, possibly leaving a corpse.
+	/// Its experience goes to the party only if one of them dealt the killing blow.
+	fn process_deaths(&mut self, console: impl console::Handle) {
+		let dead = self
+			.characters
+			.iter()
+			.filter(|character| character.borrow().hp <= 0)
+			.cloned()
+			.collect::<Vec<_>>();
+		for character in dead {
+			let killer = character.borrow_mut().killer.take();
+			if self.party.iter().any(|member| member.piece == character) {
+				let conscious = {
+					let mut piece = character.borrow_mut();
+					if piece.components.contains_key(":downed") {
+						continue;
+					}
+					piece.components.insert(":downed".into(), Value::Unit);
+					console
+						.print_defeat("{Address} collapse{s}!".replace_nouns(&piece.sheet.nouns));
+					piece.components.remove(":conscious")
+				};
+				if let Some(conscious) = conscious
+					&& let Some(next) = self.standing_party().next()
+				{
+					next.borrow_mut()
+						.components
+						.insert(":conscious".into(), conscious);
+				}
+			} else {
+				self.characters.retain(|x| *x != character);
+				self.defeated += 1;
+				let piece = character.borrow();
+				console.print_defeat("{Address} {are} defeated.".replace_nouns(&piece.sheet.nouns));
+				if piece.sheet.corpse {
+					self.items.push(item::Piece {
+						item: Item {
+							name: format!("{}'s corpse", piece.sheet.nouns.name),
+						},
+						x: piece.x,
+						y: piece.y,
+					});
+				}
+				let killed_by_party = killer
+					.is_some_and(|killer| self.party.iter().any(|member| member.piece == killer));
+				if !killed_by_party {
+					continue;
+				}
+				for member in self.standing_party() {
+					if member.gain_experience(piece.sheet.experience) > 0 {
+						let member = member.borrow();
+						console.print_special(
+							format!("{{Address}} reached level {}!", member.level)
+								.replace_nouns(&member.sheet.nouns),
+						);
+					}
+				}
+			}
+		}
+	}
+
+	/// Party members which haven't been downed.
+	pub fn standing_party(&self) -> impl Iterator<Item = &character::Ref> {
+		self.party
+			.iter()
+			.map(|member| &member.piece)
+			.filter(|piece| !piece.borrow().components.contains_key(":downed"))
+	}
+
 	fn act(
 		&mut self,
 		ability: Rc<Ability>,